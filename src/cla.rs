@@ -1,61 +1,1363 @@
 use std::fmt::{Display, Formatter};
 use std::fs::read_to_string;
-use std::path::PathBuf;
-use std::{env, fs};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io, process};
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use glob::{MatchOptions, Pattern};
+use ignore::{DirEntry, WalkBuilder};
+use me::ExecutionMode;
 use regex::Regex;
-use walkdir::WalkDir;
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// The environment variable holding the name of the MARKDOWN file to parse when `--file-name` is
+/// not provided.
+const FILE_NAME_ENV: &str = "ME_FILE";
+
+/// The shells read from the `SHELL` environment variable that can run the generated shell script.
+const POSIX_SHELLS: &[&str] = &[
+    "sh", "ash", "bash", "dash", "ksh", "mksh", "posh", "yash", "zsh",
+];
+
+/// The commands refused by `--safe`.
+const SAFE_DENY_PATTERNS: &[&str] = &[
+    r"\brm\s+(-\S+\s+)*(/|~|\$HOME)/?\*?(\s|;|&|\||$)",
+    r"\bmkfs(\.\w+)?\b",
+    r"\bdd\b.*\bof=/dev/",
+    r">\s*/dev/(sd|hd|nvme|disk)",
+    r":\(\)\s*\{\s*:\s*\|\s*:\s*&\s*\}\s*;\s*:",
+    r"\b(curl|wget)\b.*\|\s*(sudo\s+)?(ba|z)?sh\b",
+];
 
 /// A simple application that parses markdown files and executes the shell code blocks.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct Args {
-    /// Name of the MARKDOWN file to parse
+    /// Name of the MARKDOWN file to parse.  Use `-` to read the MARKDOWN from the standard input.
+    /// Defaults to the `ME_FILE` environment variable, when set, which takes precedence over the
+    /// configuration file, and then to `README.md`.
     #[arg(short, long, default_value = "README.md")]
     file_name: String,
 
-    /// Skips all commands that match the provided regular expression.  Nothing happens if the given
-    /// regular expression does not match any commands.
+    /// Reads the MARKDOWN from the standard input instead of a file, and runs the commands from the
+    /// current working directory.  Same as `--file-name -`.
+    #[arg(long, conflicts_with_all = ["file_name", "recursive", "glob", "from_list"])]
+    stdin: bool,
+
+    /// Reads the MARKDOWN from the standard input when `-` is provided, such as `me -`.  Same as
+    /// `--stdin`.
+    #[arg(value_name = "-", value_parser = ["-"], conflicts_with_all = ["file_name", "recursive", "glob", "from_list"])]
+    stdin_marker: Option<String>,
+
+    /// Only runs the code blocks annotated with the provided tag, using a `# @tag <TAG>` comment
+    /// line within the code block.  Fails if no code block has this tag.
+    #[arg(short, long)]
+    tag: Option<String>,
+
+    /// Only runs the code blocks of the provided group, set with the `group` attribute, such as
+    /// ```` ```shell group=setup ````.  The code blocks of the same group run one after the other,
+    /// from where the first one is found.  Fails if no code block has this group.
+    #[arg(long)]
+    group: Option<String>,
+
+    /// Only runs the code blocks under the MARKDOWN heading with the provided text, such as `Build`
+    /// for `## Build`, up to the next heading of the same or a higher level.  Fails if no heading
+    /// has this text.
+    #[arg(long)]
+    section: Option<String>,
+
+    /// Runs the commands of the code blocks written in the provided language.  Can be repeated to
+    /// run the code blocks of more than one language, such as `shell` and `bash`, in the order
+    /// these appear in the MARKDOWN file.
+    #[arg(long = "language", value_name = "LANGUAGE", default_value = "shell")]
+    languages: Vec<String>,
+
+    /// Removes the provided prompt, such as `$ ` or `% `, from the start of the command lines.  Can
+    /// be repeated to remove any of the provided prompts.  Note that using `# ` as a prompt turns
+    /// shell comments into commands, but does not affect the `# @tag` annotations.  The `$ ` prompt
+    /// is also removed when written without its space before a path, such as `$./gradlew build`.
+    #[arg(long = "prompt", value_name = "PROMPT", default_value = "$ ")]
+    prompts: Vec<String>,
+
+    /// Also removes the `$ ` prompt written without its space before a word starting with a
+    /// lowercase letter, such as `$echo hi`, at the start of a line.  Beware that a variable, such
+    /// as `$editor "$f"`, cannot be told apart from such a command and loses its `$`.
+    #[arg(long)]
+    prompt_without_space: bool,
+
+    /// Skips all commands that match the provided regular expression.  A warning is printed if the
+    /// given regular expression does not match any commands, as this may be caused by a typo.
     #[arg(short, long)]
     skip_commands: Option<Regex>,
 
+    /// Skips all commands that match any of the regular expressions read from the provided file,
+    /// one per line, in addition to `--skip-commands`.  Empty lines and lines starting with `#`
+    /// are ignored.
+    #[arg(long, value_name = "PATH")]
+    skip_commands_file: Option<PathBuf>,
+
+    /// Refuses to run the commands of a MARKDOWN file if any of them matches the provided regular
+    /// expression, such as `rm -rf /`.  Unlike `--skip-commands`, nothing runs and the offending
+    /// command is reported.  Can be repeated.
+    #[arg(long, value_name = "REGEX")]
+    deny: Vec<Regex>,
+
+    /// Refuses to run the commands that are likely to damage the system, such as removing the
+    /// root or home directory, formatting a disk or piping a download into a shell, in addition to
+    /// those provided with `--deny`.
+    #[arg(long)]
+    safe: bool,
+
+    /// Runs the commands matching the provided regular expression with `sudo`, such as
+    /// `^apt-get`, prefixing each matching line of the code blocks.  Beware that these commands
+    /// run with elevated privileges, that `sudo` may prompt for a password, and that a loose
+    /// regular expression may elevate commands that were not meant to be, so review the commands
+    /// with `--dry-run` first.
+    #[arg(long, value_name = "REGEX")]
+    sudo_match: Option<Regex>,
+
+    /// Starts running from the code block containing the provided command, skipping the code blocks
+    /// before it.  The command can be given with or without its prompt, such as `$ echo foo` or
+    /// `echo foo`.  Fails if no code block contains this command.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "from_list")]
+    execute_from: Option<String>,
+
+    /// Stops running after the code block containing the provided command, skipping the code
+    /// blocks after it.  The command can be given with or without its prompt, such as
+    /// `$ echo foo` or `echo foo`.  Fails if no code block contains this command.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "from_list")]
+    execute_until: Option<String>,
+
+    /// Starts running from the code block containing a command that starts with the provided
+    /// prefix, such as `docker build`.  Cannot be combined with `--execute-from`.
+    #[arg(long, value_name = "PREFIX", conflicts_with_all = ["from_list", "execute_from"])]
+    execute_from_prefix: Option<String>,
+
+    /// Stops running after the code block containing a command that starts with the provided
+    /// prefix.  Cannot be combined with `--execute-until`.
+    #[arg(long, value_name = "PREFIX", conflicts_with_all = ["from_list", "execute_until"])]
+    execute_until_prefix: Option<String>,
+
+    /// Prints the number and the text of the lines matched by `--execute-from` and
+    /// `--execute-until`, or their prefix variants, to the standard error.  Useful when the same
+    /// command is found more than once, as only the first match is used.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Skips the first N commands, such as prerequisites that were already run.  Applies after
+    /// `--execute-from`, `--execute-until` and `--tag`, and fails if fewer commands are found.
+    #[arg(long, value_name = "N", default_value = "0")]
+    skip_first: usize,
+
+    /// Only runs the first command of each MARKDOWN file, such as a `--version` check used as a
+    /// smoke test.  Applies after `--execute-from`, `--execute-until`, `--tag` and `--skip-first`.
+    #[arg(long)]
+    first_only: bool,
+
+    /// Runs the commands of each MARKDOWN file in a random order, to find commands that depend on
+    /// the commands before them without saying so.  The order is derived from the provided SEED,
+    /// or from a random seed which is printed, so that the same order can be run again.
+    /// Multi-line commands and the code blocks of the same group are moved as a whole.  The
+    /// commands still stop at the first failing command.
+    #[arg(long, num_args = 0..=1, value_name = "SEED")]
+    shuffle: Option<Option<u64>>,
+
     /// Searches for MARKDOWN files, named README.md or the provided file name, in the
-    /// subdirectories and execute each MARKDOWN file from the directory it was found.
-    #[arg(short, long, num_args = 0..=1, value_name = "DEPTH", default_missing_value = "2")]
+    /// subdirectories and execute each MARKDOWN file from the directory it was found.  A DEPTH of
+    /// 1 only searches the current directory, 2 (the default) also searches its immediate
+    /// subdirectories, and so on, while 0 searches all subdirectories without a limit.  Files and
+    /// directories ignored by the `.gitignore` files found while searching are skipped, unless
+    /// `--no-ignore` is provided.  Files and directories listed in the `.meignore` files found
+    /// while searching are always skipped, even with `--no-ignore`.  These use the same glob
+    /// syntax as `.gitignore`, apply to the directory they are in and its subdirectories, and take
+    /// precedence over the `.gitignore` files.
+    #[arg(
+        short,
+        long,
+        visible_alias = "max-depth",
+        num_args = 0..=1,
+        value_name = "DEPTH",
+        default_missing_value = "2"
+    )]
     recursive: Option<usize>,
+
+    /// Searches all subdirectories when searching for MARKDOWN files recursively, including those
+    /// ignored by the `.gitignore` files found along the way.
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Only runs the MARKDOWN files changed since the provided git reference, such as `HEAD~1` or
+    /// `main`, as listed by `git diff --name-only <REF>`.  Fails when the current directory is not
+    /// within a git repository.
+    #[arg(long, value_name = "REF", conflicts_with = "from_list")]
+    since: Option<String>,
+
+    /// Runs the commands from a file written in the format printed by `--list`, instead of a
+    /// MARKDOWN file.  In this format commands are separated by an empty line and every other line
+    /// of a multi-line command starts with a tab.  The commands run from the file's directory.
+    #[arg(long, visible_alias = "replay", value_name = "FILE", conflicts_with_all = ["recursive", "glob"])]
+    from_list: Option<PathBuf>,
+
+    /// Runs all MARKDOWN files matching the provided glob pattern, such as `docs/**/*.md`, in
+    /// sorted order.  Takes precedence over the `--recursive` and `--file-name` options.  Fails if
+    /// no files match the given pattern.
+    #[arg(short, long, value_name = "PATTERN")]
+    glob: Option<Pattern>,
+
+    /// Skips the files and directories whose name matches the provided glob pattern, such as
+    /// `target` or `node_modules`, when searching for MARKDOWN files recursively.  Excluding a
+    /// directory skips all of its subdirectories.  Can be repeated.  Excluded files are skipped even
+    /// when a `.meignore` file re-includes these with a `!` pattern.
+    #[arg(short, long, value_name = "PATTERN")]
+    exclude: Vec<Pattern>,
+
+    /// Runs the provided command before the commands of the MARKDOWN file, such as `set -u`.  Can
+    /// be repeated, and the commands run in the order these are provided.
+    #[arg(long, value_name = "COMMAND")]
+    prepend: Vec<String>,
+
+    /// Runs the provided command after the commands of the MARKDOWN file, such as a cleanup
+    /// script.  Can be repeated, and the commands run in the order these are provided.
+    #[arg(long, value_name = "COMMAND")]
+    append: Vec<String>,
+
+    /// Runs all commands the provided number of times, printing how long each iteration took to
+    /// the standard error, in seconds with millisecond precision where `date` supports it.  The
+    /// execution stops at the first failing command, as usual.
+    #[arg(long, value_name = "N", default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+    repeat: u16,
+
+    /// Runs each failing command again, up to the provided number of attempts in total, before
+    /// failing.  Each attempt runs in a subshell, which stops at the command's first failing line,
+    /// so a command changing the current directory or setting variables does not affect the
+    /// following commands.
+    #[arg(long, value_name = "ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    retry: Option<u32>,
+
+    /// The delay, in milliseconds, before the first retry.  The delay doubles after every attempt.
+    #[arg(
+        long,
+        value_name = "MILLIS",
+        default_value = "1000",
+        requires = "retry"
+    )]
+    retry_delay: u32,
+
+    /// Waits the provided duration between one command and the next, such as to give a service
+    /// started by a command time to be ready.  The duration is written in milliseconds, seconds or
+    /// minutes, such as `500ms`, `2s` or `1m`, and defaults to milliseconds without a unit.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = Self::parse_duration,
+        conflicts_with = "retry"
+    )]
+    delay: Option<u32>,
+
+    /// Runs up to the provided number of commands at the same time, for commands that do not
+    /// depend on each other.  The commands run in batches, and a failing command does not stop
+    /// the other commands of its batch, but fails the shell script once the batch completes.
+    /// Each command runs in its own subshell, and cannot be combined with the tap format.
+    #[arg(
+        long,
+        value_name = "JOBS",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["retry", "delay"]
+    )]
+    parallel: Option<u32>,
+
+    /// Fails the commands that take longer than the provided number of seconds to complete.  This
+    /// relies on the `timeout` utility, and the commands run without a time limit, with a warning,
+    /// when this is missing.  Note that with a timeout, each command runs in its own shell, and
+    /// changes to the current directory or variables are not seen by the following commands.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u32).range(1..))]
+    timeout: Option<u32>,
+
+    /// Fails the commands that use undefined variables, using `set -u`.  When the shell script is
+    /// run by `bash` or `zsh`, commands also fail when any command of a pipeline fails, using
+    /// `set -o pipefail`, which other shells may not support.
+    #[arg(long)]
+    strict: bool,
+
+    /// Runs each command in its own subshell, such that a command changing the current directory
+    /// or setting variables does not affect the following commands.
+    #[arg(long)]
+    isolate: bool,
+
+    /// Hides the output of the commands that succeed and only shows the output of the failing
+    /// command, including its standard error, which is useful to keep CI logs short.  Cannot be
+    /// combined with `--parallel`, as the output of the commands running together cannot be told
+    /// apart.
+    #[arg(long, conflicts_with = "parallel")]
+    quiet_success: bool,
+
+    /// Prints the comments found within the code blocks, the lines starting with `# `, when these
+    /// are reached while running the commands, such as to narrate the steps of a tutorial.
+    /// Otherwise, these comments are ignored by the shell.
+    #[arg(long)]
+    echo_comments: bool,
+
+    /// Stops at the first MARKDOWN file whose commands fail, without running the commands of the
+    /// remaining files.  This is the default.
+    #[arg(long, overrides_with = "no_fail_fast")]
+    fail_fast: bool,
+
+    /// Runs the commands of all MARKDOWN files, even when the commands of some files fail.  The
+    /// files whose commands failed are listed once all files are run.
+    #[arg(long, overrides_with = "fail_fast")]
+    no_fail_fast: bool,
+
+    /// Prints a `[i/n] Running <PATH>` line to the standard error before running the commands of
+    /// each MARKDOWN file, which helps following the progress of recursive runs.
+    #[arg(long)]
+    progress: bool,
+
+    /// Fails when a MARKDOWN file has no commands, such as to check that the documentation
+    /// contains runnable examples.  Applies to each MARKDOWN file, and with `--recursive`, every
+    /// file found must have commands.
+    #[arg(long)]
+    require_commands: bool,
+
+    /// Skips the MARKDOWN files that cannot be read, such as those deleted after being found while
+    /// searching recursively, with a warning.  Otherwise, a file that cannot be read fails the run.
+    #[arg(long)]
+    allow_missing_file: bool,
+
+    /// Runs the commands from the provided directory instead of the MARKDOWN file's directory.  A
+    /// relative path is resolved against the current working directory.  Takes precedence over the
+    /// `# @workdir` directive.
+    #[arg(short, long, value_name = "PATH", value_parser = Self::parse_working_dir)]
+    working_dir: Option<PathBuf>,
+
+    /// Sets an environment variable, in the `KEY=VALUE` format, for the commands.  These take
+    /// precedence over the environment variables inherited from the parent process.  Can be
+    /// repeated.  The `ME_DIR` environment variable is always set to the absolute path of the
+    /// MARKDOWN file's directory, even with `--working-dir`, such as to run `$ME_DIR/script.sh`.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = Self::parse_env)]
+    envs: Vec<(String, String)>,
+
+    /// Writes the generated shell script to the provided path, where it is kept after the commands
+    /// run.  Combined with `--dry-run`, the shell script is written but not executed.  Fails when
+    /// more than one MARKDOWN file is found, as these cannot share the same shell script.
+    #[arg(short, long, value_name = "PATH", conflicts_with = "list")]
+    output: Option<PathBuf>,
+
+    /// Precedes each command in the generated shell script with a `# command <N>` comment, which
+    /// helps relating the shell script to the MARKDOWN file when debugging.
+    #[arg(long)]
+    annotate: bool,
+
+    /// The shell that runs the commands, such as `/bin/bash`, which defaults to the `SHELL`
+    /// environment variable, or to `/bin/sh` when this is not set or not a POSIX shell, such as
+    /// `fish`.  The generated shell script starts with the matching shebang.
+    #[arg(long, value_name = "PATH", conflicts_with = "shebang")]
+    shell: Option<PathBuf>,
+
+    /// Replaces the first line of the generated shell script, `#!/bin/sh`, such as with
+    /// `#!/usr/bin/env bash` to run the commands using another shell.  The shell script is then
+    /// run using this shebang, ignoring the `SHELL` environment variable.
+    #[arg(long, value_name = "LINE")]
+    shebang: Option<String>,
+
+    /// Generates the shell script without its preamble, that is, without the shebang, the comment
+    /// and `set -e`, for commands that set their own shell options.  All commands then run, even
+    /// when some fail, unless the commands set `set -e` themselves.
+    #[arg(long, conflicts_with = "shebang")]
+    no_preamble: bool,
+
+    /// Developer tool: prints the parser's state after each line of the MARKDOWN files to the
+    /// standard error, to troubleshoot how a MARKDOWN file is parsed.  Combine it with
+    /// `--no-exec` to only parse the files.  The format may change at any time.
+    #[arg(long, hide = true)]
+    dump_ast: bool,
+
+    /// Prints each command, with its expansions, to the standard error as the shell runs it, using
+    /// `set -x`.  Useful to debug what the generated shell script actually does.
+    #[arg(long)]
+    trace: bool,
+
+    /// The line printed before each command, which helps telling where the output of one command
+    /// ends and the next command starts.
+    #[arg(
+        long,
+        value_name = "SEPARATOR",
+        default_value = "---",
+        allow_hyphen_values = true
+    )]
+    separator: String,
+
+    /// Prints no line before each command, such that only the commands and their output are
+    /// printed.
+    #[arg(long, conflicts_with = "separator")]
+    no_separator: bool,
+
+    /// Writes the output of the commands of each MARKDOWN file to a log file named after it, such
+    /// as `README.md.me.log`, next to the MARKDOWN file, in addition to printing it.
+    #[arg(long)]
+    log: bool,
+
+    /// Prefixes each line printed by the commands with the number of the command that printed it,
+    /// such as `[1] `, which helps telling apart the output of the commands.
+    #[arg(long)]
+    prefix_output: bool,
+
+    /// Prints the output selected by `--format` instead of running the commands.  Only one of the
+    /// non-running options `--no-exec`, `--dry-run` and `--list` can be given, and providing more
+    /// than one fails, as does providing `--format` with `--dry-run` or `--list`.
+    #[arg(long)]
+    no_exec: bool,
+
+    /// The output printed by `--no-exec`, or `tap` to run the commands and report the result of
+    /// each in the Test Anything Protocol format.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// Prints the generated shell script instead of running it.  Same as `--no-exec --format
+    /// script`.
+    #[arg(long, conflicts_with_all = ["no_exec", "list", "format"])]
+    dry_run: bool,
+
+    /// Prints the commands instead of running them, in the format read by `--from-list`.  Same as
+    /// `--no-exec --format list`.
+    #[arg(long, conflicts_with_all = ["no_exec", "format"])]
+    list: bool,
+
+    /// Prints the path of each MARKDOWN file that would be run, one per line, without parsing or
+    /// running their commands.  Useful to check which files `--recursive`, `--exclude` and `--glob`
+    /// pick up.
+    #[arg(long, visible_alias = "print-path", conflicts_with_all = ["no_exec", "dry_run", "list"])]
+    print_files: bool,
+
+    /// Prints the configuration resolved from the options and the configuration file, such as the
+    /// MARKDOWN files to run, the execution mode and the shell, one `key: value` line each, without
+    /// running the commands.  Useful to understand which options take precedence.
+    #[arg(long, conflicts_with_all = ["no_exec", "dry_run", "list", "print_files"])]
+    explain: bool,
+
+    /// Prints the number of commands of each MARKDOWN file, as `<PATH>: <N>`, followed by a
+    /// `total: <N>` line, without running the commands.  The prepended and appended commands are
+    /// not counted.
+    #[arg(long, conflicts_with_all = ["no_exec", "dry_run", "list", "print_files", "explain"])]
+    count: bool,
+
+    /// Checks the syntax of the generated shell script, using the shell's `-n` option, without
+    /// running the commands, such as to find unbalanced quotes or unterminated here-documents.
+    /// The shell's error messages are printed, and the MARKDOWN files with syntax errors fail.
+    #[arg(long, conflicts_with_all = ["no_exec", "dry_run", "list", "print_files", "explain", "count", "format"])]
+    check: bool,
+
+    /// The prefix of the temporary shell script's file name, which is followed by the process id
+    /// and the time the shell script was created.  Useful when other tools look for files named
+    /// after a specific pattern.
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = ".me-commands-",
+        conflicts_with = "output"
+    )]
+    script_prefix: String,
+
+    /// Prints the errors, such as a MARKDOWN file that cannot be parsed or whose commands fail, to
+    /// the standard error as text, or as JSON objects, one per line, holding the `file`, the
+    /// `kind` of error, its `message` and the `line` on which it was found, if known.  The `file`
+    /// is `null` for the errors that do not relate to a file, such as a glob pattern that matches
+    /// no files.  The warnings, such as a skipped MARKDOWN file, are printed the same way.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    error_format: ErrorFormat,
+
+    /// Reads the default options from the provided TOML file instead of the `.me.toml` file in the
+    /// current directory.  The keys are named after the options, such as `file_name`,
+    /// `skip_commands` or `recursive`, and the options provided on the command line take
+    /// precedence over those read from the file.  The `skip_commands` of specific files can be
+    /// replaced using `[[files]]` tables, holding the `path` glob pattern of the files, relative to
+    /// the current directory, and their `skip_commands`.  The first matching table applies.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// The commands to skip in the MARKDOWN files matching each pattern, read from the `[[files]]`
+    /// tables of the configuration file.
+    #[arg(skip)]
+    file_skip_commands: Vec<(Pattern, Option<Regex>)>,
+}
+
+/// The output printed instead of running the commands.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// The generated shell script
+    Script,
+    /// The commands, in the format read by `--from-list`
+    List,
+    /// The commands as a JSON array, meant to be read by other tools
+    Json,
+    /// Runs the commands, and reports the result of each in the Test Anything Protocol (TAP)
+    /// format on the standard output, while the output of the commands is written to the
+    /// standard error.  Each command runs in its own subshell.  Unlike the other formats, this
+    /// cannot be used with `--no-exec`, nor with `--parallel` or `--quiet-success`
+    Tap,
+}
+
+/// How the errors are printed.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ErrorFormat {
+    /// Human readable messages
+    Text,
+    /// JSON objects, one per line, meant to be read by other tools
+    Json,
+}
+
+/// An error printed by `--error-format json`, whose `file` is `None` when the error does not relate
+/// to a specific file.
+#[derive(Debug, Serialize)]
+pub(crate) struct ErrorReport<'a> {
+    pub(crate) file: Option<String>,
+    pub(crate) kind: &'a str,
+    pub(crate) message: String,
+    pub(crate) line: Option<usize>,
+}
+
+impl ErrorReport<'_> {
+    /// Prints the error to the standard error, as a JSON object on a single line.
+    pub(crate) fn print(&self) {
+        eprintln!(
+            "{}",
+            serde_json::to_string(self).expect("Failed to serialize the error")
+        );
+    }
 }
 
 impl Args {
     pub(crate) fn create() -> Self {
-        Args::parse()
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if args.safe {
+            args.deny.extend(
+                SAFE_DENY_PATTERNS
+                    .iter()
+                    .map(|pattern| Regex::new(pattern).expect("Invalid deny pattern")),
+            );
+        }
+
+        match (args.format, args.no_exec || args.dry_run || args.list) {
+            (Some(Format::Tap), _) if args.parallel.is_some() => Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "The tap format cannot be used with --parallel",
+                )
+                .exit(),
+            (Some(Format::Tap), _) if args.quiet_success => Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "The tap format cannot be used with --quiet-success",
+                )
+                .exit(),
+            (Some(Format::Tap), true) => Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "The tap format runs the commands and cannot be used with --no-exec, --dry-run or --list",
+                )
+                .exit(),
+            (Some(Format::Script | Format::List | Format::Json), false) => Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "The --format option requires --no-exec, unless the format is tap",
+                )
+                .exit(),
+            _ => {}
+        }
+
+        if let Some(None) = args.shuffle {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+            eprintln!("Shuffling the commands with seed {}", seed);
+            args.shuffle = Some(Some(seed));
+        }
+
+        /* Set beforehand, such that the recursive search of the configuration file does not apply */
+        if args.stdin_marker.is_some() || args.file_name == MarkdownFile::STDIN {
+            args.stdin = true;
+        }
+
+        let merged = Config::load(args.config.as_deref())
+            .and_then(|config| config.map_or(Ok(()), |config| args.merge(config, &matches)));
+        if let Err(message) = merged {
+            let path = args
+                .config
+                .as_deref()
+                .unwrap_or(Path::new(Config::FILE_NAME));
+            let file = Some(path.display().to_string());
+            args.exit_with_error(ErrorKind::InvalidValue, "config", file, message);
+        }
+        if let Err(message) = args.read_skip_commands_file() {
+            let file = args
+                .skip_commands_file
+                .as_ref()
+                .map(|path| path.display().to_string());
+            args.exit_with_error(ErrorKind::InvalidValue, "skip-commands-file", file, message);
+        }
+
+        /* Applied after the configuration file, as clap sets the default file name beforehand */
+        if matches.value_source("file_name") != Some(ValueSource::CommandLine) {
+            if let Some(file_name) = env::var(FILE_NAME_ENV).ok().filter(|f| !f.is_empty()) {
+                args.file_name = file_name;
+            }
+        }
+
+        /* Reading from `-`, whether positional, from `--file-name` or otherwise, is the same as `--stdin` */
+        if args.stdin || args.file_name == MarkdownFile::STDIN {
+            let recursive = matches.value_source("recursive") == Some(ValueSource::CommandLine);
+            if recursive || args.glob.is_some() || args.from_list.is_some() {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "The MARKDOWN cannot be read from the standard input with --recursive, --glob or --from-list",
+                    )
+                    .exit();
+            }
+            args.stdin = true;
+            args.recursive = None;
+        }
+
+        /* Checked after the configuration file, which may set these options too */
+        #[cfg(windows)]
+        {
+            let unsupported: Vec<&str> = [
+                ("--timeout", args.timeout.is_some()),
+                ("--retry", args.retry.is_some()),
+                ("--delay", args.delay.is_some()),
+                ("--parallel", args.parallel.is_some()),
+                ("--repeat", args.repeat > 1),
+                ("--format tap", args.format == Some(Format::Tap)),
+                ("--quiet-success", args.quiet_success),
+                ("--isolate", args.isolate),
+                ("--strict", args.strict),
+                ("--trace", args.trace),
+                ("--echo-comments", args.echo_comments),
+            ]
+            .into_iter()
+            .filter(|(_, set)| *set)
+            .map(|(option, _)| option)
+            .collect();
+            if !unsupported.is_empty() {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!(
+                            "The PowerShell script run on Windows does not support {}",
+                            unsupported.join(", ")
+                        ),
+                    )
+                    .exit();
+            }
+        }
+
+        args
+    }
+
+    /// Sets the options read from the configuration file, except for those that were provided on
+    /// the command line.
+    fn merge(&mut self, config: Config, matches: &ArgMatches) -> Result<(), String> {
+        let provided = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        let skip_commands = config
+            .skip_commands
+            .map(|regex| Regex::new(&regex).map(Some))
+            .transpose()
+            .map_err(|e| format!("invalid `skip_commands` in the configuration file: {}", e))?;
+        let exclude = config
+            .exclude
+            .map(|patterns| patterns.iter().map(|p| Pattern::new(p)).collect())
+            .transpose()
+            .map_err(|e| format!("invalid `exclude` in the configuration file: {}", e))?;
+        if config.repeat == Some(0) {
+            return Err("`repeat` in the configuration file must be at least 1".to_string());
+        }
+        if config.retry == Some(0) {
+            return Err("`retry` in the configuration file must be at least 1".to_string());
+        }
+        if config.parallel == Some(0) {
+            return Err("`parallel` in the configuration file must be at least 1".to_string());
+        }
+        if config.parallel.is_some() && config.quiet_success == Some(true) {
+            return Err(
+                "`parallel` and `quiet_success` cannot both be set in the configuration file"
+                    .to_string(),
+            );
+        }
+        let execution_modes = [
+            config.retry.is_some(),
+            config.delay.is_some(),
+            config.parallel.is_some(),
+        ];
+        if execution_modes.into_iter().filter(|&set| set).count() > 1 {
+            return Err(
+                "only one of `retry`, `delay` and `parallel` can be set in the configuration file"
+                    .to_string(),
+            );
+        }
+        let delay = config
+            .delay
+            .map(|delay| Self::parse_duration(&delay).map(Some))
+            .transpose()
+            .map_err(|e| format!("invalid `delay` in the configuration file: {}", e))?;
+        let file_skip_commands = config
+            .files
+            .unwrap_or_default()
+            .into_iter()
+            .map(|file| {
+                let pattern = Pattern::new(&file.path).map_err(|e| {
+                    format!("invalid `path` of `files` in the configuration file: {}", e)
+                })?;
+                let skip_commands = file
+                    .skip_commands
+                    .map(|regex| Regex::new(&regex))
+                    .transpose()
+                    .map_err(|e| {
+                        format!(
+                            "invalid `skip_commands` of `files` in the configuration file: {}",
+                            e
+                        )
+                    })?;
+                Ok((pattern, skip_commands))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        /* The recursive search does not apply when the MARKDOWN is read from elsewhere */
+        let other_source = self.stdin || self.from_list.is_some() || self.glob.is_some();
+
+        Self::merge_value(&mut self.file_name, config.file_name, provided("file_name"));
+        Self::merge_value(&mut self.tag, config.tag.map(Some), provided("tag"));
+        Self::merge_value(&mut self.languages, config.languages, provided("languages"));
+        Self::merge_value(&mut self.prompts, config.prompts, provided("prompts"));
+        Self::merge_value(
+            &mut self.skip_commands,
+            skip_commands,
+            provided("skip_commands"),
+        );
+        Self::merge_value(
+            &mut self.recursive,
+            config.recursive.map(Some),
+            provided("recursive") || other_source,
+        );
+        Self::merge_value(
+            &mut self.file_skip_commands,
+            Some(file_skip_commands),
+            provided("skip_commands") || provided("skip_commands_file"),
+        );
+        Self::merge_value(&mut self.no_ignore, config.no_ignore, provided("no_ignore"));
+        Self::merge_value(&mut self.exclude, exclude, provided("exclude"));
+        Self::merge_value(&mut self.repeat, config.repeat, provided("repeat"));
+        /* An execution mode provided on the command line replaces the one of the configuration file */
+        let execution_mode_provided =
+            provided("retry") || provided("delay") || provided("parallel");
+        Self::merge_value(
+            &mut self.retry,
+            config.retry.map(Some),
+            execution_mode_provided,
+        );
+        Self::merge_value(&mut self.delay, delay, execution_mode_provided);
+        /* The tap format cannot run the commands in parallel */
+        Self::merge_value(
+            &mut self.parallel,
+            config.parallel.map(Some),
+            execution_mode_provided || self.format == Some(Format::Tap) || self.quiet_success,
+        );
+        Self::merge_value(
+            &mut self.retry_delay,
+            config.retry_delay,
+            provided("retry_delay"),
+        );
+        Self::merge_value(
+            &mut self.timeout,
+            config.timeout.map(Some),
+            provided("timeout"),
+        );
+        Self::merge_value(
+            &mut self.quiet_success,
+            config.quiet_success,
+            provided("quiet_success") || self.format == Some(Format::Tap) || provided("parallel"),
+        );
+        Self::merge_value(&mut self.annotate, config.annotate, provided("annotate"));
+        Self::merge_value(&mut self.trace, config.trace, provided("trace"));
+        Self::merge_value(&mut self.shell, config.shell.map(Some), provided("shell"));
+        Self::merge_value(&mut self.isolate, config.isolate, provided("isolate"));
+        Self::merge_value(&mut self.strict, config.strict, provided("strict"));
+        Self::merge_value(&mut self.separator, config.separator, provided("separator"));
+        Ok(())
+    }
+
+    /// Combines the regular expressions read from the skip commands file with the skip commands
+    /// regular expression, such that commands matching any of these are skipped.
+    fn read_skip_commands_file(&mut self) -> Result<(), String> {
+        let Some(path) = &self.skip_commands_file else {
+            return Ok(());
+        };
+
+        let content = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "failed to read the skip commands file `{}`: {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        let mut patterns: Vec<String> = self
+            .skip_commands
+            .iter()
+            .map(|regex| regex.as_str().to_string())
+            .collect();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            Regex::new(line).map_err(|e| {
+                format!(
+                    "invalid pattern in the skip commands file `{}` on line {}: {}",
+                    path.display(),
+                    index + 1,
+                    e
+                )
+            })?;
+            patterns.push(line.to_string());
+        }
+
+        if !patterns.is_empty() {
+            let combined = patterns
+                .iter()
+                .map(|pattern| format!("(?:{})", pattern))
+                .collect::<Vec<_>>()
+                .join("|");
+            self.skip_commands = Some(Regex::new(&combined).map_err(|e| e.to_string())?);
+        }
+        Ok(())
+    }
+
+    fn merge_value<T>(option: &mut T, value: Option<T>, provided: bool) {
+        if let (Some(value), false) = (value, provided) {
+            *option = value;
+        }
+    }
+
+    pub(crate) fn deny(&self) -> &[Regex] {
+        &self.deny
+    }
+
+    pub(crate) fn sudo_match(&self) -> Option<&Regex> {
+        self.sudo_match.as_ref()
     }
 
     pub(crate) fn skip_commands(&self) -> Option<&Regex> {
         self.skip_commands.as_ref()
     }
 
+    /// Returns the commands to skip in the given MARKDOWN file, read from the first `[[files]]`
+    /// table of the configuration file whose path matches, or the commands to skip in every file
+    /// otherwise.  The `[[files]]` tables are ignored when the commands to skip are provided on the
+    /// command line.
+    pub(crate) fn skip_commands_for(&self, markdown: &MarkdownFile) -> Option<&Regex> {
+        self.file_skip_commands
+            .iter()
+            .find(|(pattern, _)| markdown.matches(pattern))
+            .map_or(self.skip_commands(), |(_, regex)| regex.as_ref())
+    }
+
+    /// Returns the output to print instead of running the commands, or `None` when the commands
+    /// need to be executed.
+    pub(crate) fn no_exec_format(&self) -> Option<Format> {
+        if self.dry_run {
+            Some(Format::Script)
+        } else if self.list {
+            Some(Format::List)
+        } else if self.no_exec {
+            Some(self.format.unwrap_or(Format::Script))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` when the commands are run and their results reported in the TAP format.
+    pub(crate) fn tap(&self) -> bool {
+        self.format == Some(Format::Tap)
+    }
+
+    pub(crate) fn working_dir(&self) -> Option<PathBuf> {
+        self.working_dir.clone()
+    }
+
+    fn parse_working_dir(path: &str) -> Result<PathBuf, String> {
+        fs::canonicalize(path).map_err(|e| format!("failed to resolve `{}`: {}", path, e))
+    }
+
+    pub(crate) fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    pub(crate) fn script_prefix(&self) -> &str {
+        &self.script_prefix
+    }
+
+    pub(crate) fn envs(&self) -> &[(String, String)] {
+        &self.envs
+    }
+
+    fn parse_env(env: &str) -> Result<(String, String), String> {
+        match env.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(format!("expected KEY=VALUE but found `{}`", env)),
+        }
+    }
+
+    pub(crate) fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub(crate) fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub(crate) fn section(&self) -> Option<&str> {
+        self.section.as_deref()
+    }
+
+    pub(crate) fn execute_from(&self) -> Option<&str> {
+        self.execute_from.as_deref()
+    }
+
+    pub(crate) fn execute_until(&self) -> Option<&str> {
+        self.execute_until.as_deref()
+    }
+
+    pub(crate) fn execute_from_prefix(&self) -> Option<&str> {
+        self.execute_from_prefix.as_deref()
+    }
+
+    pub(crate) fn execute_until_prefix(&self) -> Option<&str> {
+        self.execute_until_prefix.as_deref()
+    }
+
+    pub(crate) fn skip_first(&self) -> usize {
+        self.skip_first
+    }
+
+    pub(crate) fn shuffle(&self) -> Option<u64> {
+        self.shuffle.flatten()
+    }
+
+    pub(crate) fn verbose(&self) -> bool {
+        self.verbose
+    }
+
+    pub(crate) fn first_only(&self) -> bool {
+        self.first_only
+    }
+
+    pub(crate) fn prompts(&self) -> &[String] {
+        &self.prompts
+    }
+
+    pub(crate) fn prompt_without_space(&self) -> bool {
+        self.prompt_without_space
+    }
+
+    pub(crate) fn prepend(&self) -> &[String] {
+        &self.prepend
+    }
+
+    pub(crate) fn append(&self) -> &[String] {
+        &self.append
+    }
+
+    pub(crate) fn repeat(&self) -> usize {
+        self.repeat as usize
+    }
+
+    pub(crate) fn timeout(&self) -> Option<u32> {
+        self.timeout
+    }
+
+    pub(crate) fn annotate(&self) -> bool {
+        self.annotate
+    }
+
+    /// Returns `true` if the parser's state is printed after each line, a developer tool.
+    pub(crate) fn dump_ast(&self) -> bool {
+        self.dump_ast
+    }
+
+    pub(crate) fn no_preamble(&self) -> bool {
+        self.no_preamble
+    }
+
+    /// Returns the first line of the generated shell script, when this is not `#!/bin/sh`.
+    pub(crate) fn shebang(&self) -> Option<String> {
+        self.shebang
+            .clone()
+            .or_else(|| self.shell().map(|shell| format!("#!{}", shell.display())))
+    }
+
+    /// Returns the shell that runs the commands, from the `--shell` option or the `SHELL`
+    /// environment variable, or `None` to run these using `/bin/sh`, or using the shebang when one
+    /// is provided.  The `SHELL` environment variable is ignored when this is not a POSIX shell,
+    /// such as `fish` or `csh`.
+    pub(crate) fn shell(&self) -> Option<PathBuf> {
+        if self.shebang.is_some() {
+            return None;
+        }
+
+        self.shell.clone().or_else(|| {
+            env::var_os("SHELL").map(PathBuf::from).filter(|shell| {
+                shell
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| POSIX_SHELLS.contains(&name))
+            })
+        })
+    }
+
+    pub(crate) fn trace(&self) -> bool {
+        self.trace
+    }
+
+    pub(crate) fn separator(&self) -> Option<&str> {
+        (!self.no_separator).then_some(self.separator.as_str())
+    }
+
+    pub(crate) fn log(&self) -> bool {
+        self.log
+    }
+
+    pub(crate) fn prefix_output(&self) -> bool {
+        self.prefix_output
+    }
+
+    pub(crate) fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub(crate) fn isolate(&self) -> bool {
+        self.isolate
+    }
+
+    pub(crate) fn echo_comments(&self) -> bool {
+        self.echo_comments
+    }
+
+    pub(crate) fn quiet_success(&self) -> bool {
+        self.quiet_success
+    }
+
+    pub(crate) fn print_files(&self) -> bool {
+        self.print_files
+    }
+
+    pub(crate) fn explain(&self) -> bool {
+        self.explain
+    }
+
+    /// Returns the resolved configuration printed by `--explain`, one `key: value` line each.
+    pub(crate) fn explanation(&self) -> String {
+        let none = || "none".to_string();
+        let or_none = |value: Option<&str>| value.map_or_else(none, str::to_string);
+        let files = self
+            .files()
+            .iter()
+            .map(MarkdownFile::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let recursive = match self.recursive {
+            _ if self.stdin || self.from_list.is_some() || self.glob.is_some() => none(),
+            None => none(),
+            Some(0) => "unlimited depth".to_string(),
+            Some(depth) => format!("depth {}", depth),
+        };
+        let execution_mode = match self.execution_mode() {
+            ExecutionMode::Default => "default".to_string(),
+            ExecutionMode::RetryWithBackoff { attempts, delay_ms } => format!(
+                "retry up to {} attempts, starting with a {}ms delay",
+                attempts, delay_ms
+            ),
+            ExecutionMode::DelayBetweenCommands { delay_ms } => {
+                format!("wait {}ms between commands", delay_ms)
+            }
+            ExecutionMode::Parallel { jobs } => format!("parallel, up to {} jobs", jobs),
+        };
+        let execute = |command: Option<&str>, prefix: Option<&str>| match (command, prefix) {
+            (_, Some(prefix)) => format!("{} (prefix)", prefix),
+            (command, None) => or_none(command),
+        };
+
+        [
+            ("files", files),
+            ("recursive", recursive),
+            ("execution mode", execution_mode),
+            (
+                "shell",
+                self.shell()
+                    .map_or_else(none, |shell| shell.display().to_string()),
+            ),
+            (
+                "shebang",
+                self.shebang().unwrap_or_else(|| "#!/bin/sh".to_string()),
+            ),
+            ("languages", self.languages.join(", ")),
+            (
+                "skip commands",
+                or_none(self.skip_commands().map(Regex::as_str)),
+            ),
+            ("tag", or_none(self.tag())),
+            ("section", or_none(self.section())),
+            (
+                "execute from",
+                execute(self.execute_from(), self.execute_from_prefix()),
+            ),
+            (
+                "execute until",
+                execute(self.execute_until(), self.execute_until_prefix()),
+            ),
+            ("skip first", self.skip_first.to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| format!("{}: {}\n", key, value))
+        .collect()
+    }
+
+    pub(crate) fn error_format(&self) -> ErrorFormat {
+        self.error_format
+    }
+
+    pub(crate) fn check(&self) -> bool {
+        self.check
+    }
+
+    pub(crate) fn count(&self) -> bool {
+        self.count
+    }
+
+    pub(crate) fn allow_missing_file(&self) -> bool {
+        self.allow_missing_file
+    }
+
+    pub(crate) fn progress(&self) -> bool {
+        self.progress
+    }
+
+    pub(crate) fn require_commands(&self) -> bool {
+        self.require_commands
+    }
+
+    pub(crate) fn fail_fast(&self) -> bool {
+        !self.no_fail_fast
+    }
+
+    pub(crate) fn execution_mode(&self) -> ExecutionMode {
+        self.retry
+            .map(|attempts| ExecutionMode::RetryWithBackoff {
+                attempts,
+                delay_ms: self.retry_delay,
+            })
+            .or_else(|| {
+                self.delay
+                    .map(|delay_ms| ExecutionMode::DelayBetweenCommands { delay_ms })
+            })
+            .or_else(|| {
+                self.parallel.map(|jobs| ExecutionMode::Parallel {
+                    jobs: jobs as usize,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Parses a duration, such as `500ms`, `2s` or `1m`, into milliseconds.
+    fn parse_duration(duration: &str) -> Result<u32, String> {
+        let unit_index = duration
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(duration.len());
+        let (value, unit) = duration.split_at(unit_index);
+        let multiplier = match unit {
+            "" | "ms" => 1,
+            "s" => 1_000,
+            "m" => 60_000,
+            _ => {
+                return Err(format!(
+                    "expected a duration such as `500ms`, `2s` or `1m` but found `{}`",
+                    duration
+                ))
+            }
+        };
+
+        value
+            .parse::<u32>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .ok_or_else(|| format!("invalid duration `{}`", duration))
+    }
+
+    pub(crate) fn languages(&self) -> &[String] {
+        &self.languages
+    }
+
+    pub(crate) fn is_from_list(&self) -> bool {
+        self.from_list.is_some()
+    }
+
     pub(crate) fn files(&self) -> Vec<MarkdownFile> {
+        let mut files = self.find_files();
+
+        if let Some(reference) = &self.since {
+            let changed = Self::find_changed_files(reference).unwrap_or_else(|e| {
+                self.exit_with_error(
+                    ErrorKind::InvalidValue,
+                    "since",
+                    None,
+                    format!(
+                        "Failed to find the files changed since {}: {}",
+                        reference, e
+                    ),
+                )
+            });
+            files.retain(|file| changed.contains(&file.canonical_path()));
+        }
+
+        if self.output.is_some() && files.len() > 1 {
+            self.exit_with_error(
+                ErrorKind::ArgumentConflict,
+                "output",
+                None,
+                "The --output option cannot be used when more than one MARKDOWN file is found"
+                    .to_string(),
+            );
+        }
+
+        files
+    }
+
+    /// Prints the error and exits, as a JSON object with `--error-format json`, or as a usage
+    /// error otherwise.
+    fn exit_with_error(
+        &self,
+        kind: ErrorKind,
+        name: &str,
+        file: Option<String>,
+        message: String,
+    ) -> ! {
+        if self.error_format == ErrorFormat::Json {
+            ErrorReport {
+                file,
+                kind: name,
+                message,
+                line: None,
+            }
+            .print();
+            process::exit(2);
+        }
+
+        Args::command().error(kind, message).exit()
+    }
+
+    fn find_files(&self) -> Vec<MarkdownFile> {
+        if let Some(path) = &self.from_list {
+            return vec![MarkdownFile::new(path.clone())];
+        }
+
+        if let Some(pattern) = &self.glob {
+            return self.find_glob_files(pattern);
+        }
+
         self.recursive
-            .map(|max_depth| Self::find_markdown_files(max_depth, &self.file_name))
+            .map(|max_depth| {
+                if max_depth == 0 {
+                    usize::MAX
+                } else {
+                    max_depth
+                }
+            })
+            .map(|max_depth| self.find_markdown_files(max_depth))
             .unwrap_or_else(|| vec![MarkdownFile::new(self.file_path())])
     }
 
-    fn find_markdown_files(max_depth: usize, file_name: &str) -> Vec<MarkdownFile> {
-        WalkDir::new(env::current_dir().expect("Failed to get the current working directory"))
-            .max_depth(max_depth)
-            .sort_by_file_name()
-            .into_iter()
+    /// Returns the canonical paths of the files changed since the given git reference, ignoring
+    /// those that were deleted.
+    fn find_changed_files(reference: &str) -> Result<Vec<PathBuf>, String> {
+        let git = |args: &[&str]| {
+            let output = process::Command::new("git")
+                .args(args)
+                .output()
+                .map_err(|e| format!("failed to run git: {}", e))?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+
+        let top_level = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim_end());
+        let changed = git(&["diff", "--name-only", reference, "--"])?
+            .lines()
+            .filter_map(|path| fs::canonicalize(top_level.join(path)).ok())
+            .collect();
+        Ok(changed)
+    }
+
+    fn find_markdown_files(&self, max_depth: usize) -> Vec<MarkdownFile> {
+        let exclude = self.exclude.clone();
+
+        WalkBuilder::new(env::current_dir().expect("Failed to get the current working directory"))
+            .max_depth(Some(max_depth))
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .standard_filters(false)
+            .git_ignore(!self.no_ignore) // Only the .gitignore files found while searching
+            .add_custom_ignore_filename(".meignore") // Always skip the files listed by .meignore
+            .require_git(false)
+            .filter_entry(move |e| e.depth() == 0 || !Self::is_excluded(e, &exclude)) // Prune the excluded directories
+            .build()
             .filter_map(|e| e.ok()) // Convert iterator of `Result<DirEntry, Error>` to iterator of `DirEntry`
-            .filter(|e| e.file_type().is_file()) // Filter to only consider files
-            .filter(|e| e.file_name() == file_name) // Filter for files named "MARKDOWN.md"
+            .filter(|e| e.file_type().is_some_and(|t| t.is_file())) // Filter to only consider files
+            .filter(|e| e.file_name() == self.file_name.as_str()) // Filter for files named "MARKDOWN.md"
             .map(|e| e.into_path()) // Convert DirEntry to PathBuf
             .map(MarkdownFile::new)
             .collect()
     }
 
+    fn is_excluded(entry: &DirEntry, exclude: &[Pattern]) -> bool {
+        entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| exclude.iter().any(|pattern| pattern.matches(name)))
+    }
+
+    fn find_glob_files(&self, pattern: &Pattern) -> Vec<MarkdownFile> {
+        let mut paths: Vec<PathBuf> = glob::glob(pattern.as_str())
+            .expect("Failed to expand the glob pattern")
+            .filter_map(|e| e.ok()) // Ignore paths that could not be read
+            .filter(|p| p.is_file())
+            .collect();
+
+        if paths.is_empty() {
+            self.exit_with_error(
+                ErrorKind::ValueValidation,
+                "no-matches",
+                None,
+                format!("No MARKDOWN files match the glob pattern: {}", pattern),
+            );
+        }
+
+        paths.sort();
+        paths.into_iter().map(MarkdownFile::new).collect()
+    }
+
     fn file_path(&self) -> PathBuf {
-        PathBuf::from(&self.file_name)
+        if self.stdin {
+            PathBuf::from(MarkdownFile::STDIN)
+        } else {
+            PathBuf::from(&self.file_name)
+        }
     }
 }
 
@@ -64,13 +1366,53 @@ pub(crate) struct MarkdownFile {
 }
 
 impl MarkdownFile {
+    /// The path used to read the MARKDOWN from the standard input
+    const STDIN: &'static str = "-";
+
     fn new(path: PathBuf) -> Self {
         MarkdownFile { path }
     }
 
+    fn is_stdin(&self) -> bool {
+        self.path.as_os_str() == Self::STDIN
+    }
+
+    /// Returns `true` if the path of this file, relative to the current working directory, matches
+    /// the given glob pattern, where `*` does not match the path separator, unlike `**`.
+    fn matches(&self, pattern: &Pattern) -> bool {
+        if self.is_stdin() {
+            return false;
+        }
+
+        let current_dir = env::current_dir()
+            .and_then(fs::canonicalize)
+            .expect("Failed to get the current working directory");
+        let path = self.canonical_path();
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        pattern.matches_path_with(path.strip_prefix(&current_dir).unwrap_or(&path), options)
+    }
+
+    /// Returns the path of the log file written next to the MARKDOWN file, or in the current
+    /// working directory when reading from the standard input.
+    pub(crate) fn log_path(&self) -> PathBuf {
+        if self.is_stdin() {
+            return self.parent_dir().join("stdin.me.log");
+        }
+
+        let mut path = self.canonical_path().into_os_string();
+        path.push(".me.log");
+        PathBuf::from(path)
+    }
+
     pub(crate) fn parent_dir(&self) -> PathBuf {
-        fs::canonicalize(&self.path)
-            .expect("Failed to canonicalize path")
+        if self.is_stdin() {
+            return env::current_dir().expect("Failed to get the current working directory");
+        }
+
+        self.canonical_path()
             .parent()
             .map(|p| p.to_path_buf())
             .unwrap_or_else(|| {
@@ -78,18 +1420,50 @@ impl MarkdownFile {
             })
     }
 
-    pub(crate) fn read(&self) -> String {
+    /// Returns the working directory set by the `# @workdir <PATH>` directive, if one is found at
+    /// the top of the MARKDOWN content.  The path is resolved relative to the MARKDOWN file's
+    /// directory, and an error is returned when it does not exist.
+    pub(crate) fn working_dir(&self, content: &str) -> io::Result<Option<PathBuf>> {
+        content
+            .lines()
+            .map(|line| line.trim())
+            .take_while(|line| line.is_empty() || line.starts_with("# @"))
+            .find_map(|line| line.strip_prefix("# @workdir "))
+            .map(|path| self.parent_dir().join(path.trim()))
+            .map(|path| {
+                fs::canonicalize(&path)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            })
+            .transpose()
+    }
+
+    /// Reads the MARKDOWN content from the file, or from the standard input.
+    pub(crate) fn read(&self) -> io::Result<String> {
+        if self.is_stdin() {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content)?;
+            return Ok(content);
+        }
+
         read_to_string(&self.path)
-            .unwrap_or_else(|_| panic!("Failed to read MARKDOWN file: {}", self.path_as_str()))
     }
 
     fn path_as_str(&self) -> String {
-        fs::canonicalize(&self.path)
-            .expect("Failed to canonicalize path")
-            .as_os_str()
-            .to_str()
-            .expect("failed to convert path")
-            .to_string()
+        if self.is_stdin() {
+            return "<stdin>".to_string();
+        }
+
+        self.canonical_path().display().to_string()
+    }
+
+    /// Returns the canonical path of the MARKDOWN file, or its absolute path if this cannot be
+    /// resolved, such as when the file is deleted while searching recursively.
+    fn canonical_path(&self) -> PathBuf {
+        fs::canonicalize(&self.path).unwrap_or_else(|_| {
+            env::current_dir()
+                .map(|dir| dir.join(&self.path))
+                .unwrap_or_else(|_| self.path.clone())
+        })
     }
 }
 
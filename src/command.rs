@@ -1,44 +1,756 @@
+use std::collections::VecDeque;
 use std::fmt::{Debug, Display, Formatter};
 
 use regex::Regex;
+use serde::Serialize;
 
+/// Configures how the commands are parsed from the MARKDOWN content and how the shell script is
+/// generated, starting from [`Options::new`] and building the commands using [`Options::build`].
 #[derive(Debug)]
-pub(crate) struct Options<'a> {
+pub struct Options<'a> {
     content: &'a str,
     skip_commands: Option<&'a Regex>,
+    deny: &'a [Regex],
+    sudo_match: Option<&'a Regex>,
+    tag: Option<&'a str>,
+    group: Option<&'a str>,
+    section: Option<&'a str>,
+    execute_from: Option<&'a str>,
+    execute_until: Option<&'a str>,
+    execute_from_prefix: bool,
+    execute_until_prefix: bool,
+    skip_first: usize,
+    first_only: bool,
+    shuffle: Option<u64>,
+    languages: Vec<&'a str>,
+    prompts: Vec<&'a str>,
+    prompt_without_space: bool,
+    prepend: &'a [String],
+    append: &'a [String],
+    parser_dump: Option<fn(&str)>,
+    script: ScriptOptions,
 }
 
 impl<'a> Options<'a> {
-    pub(crate) fn new(content: &'a str) -> Self {
+    /// Creates the options to parse the given content, ignoring the UTF-8 byte order mark the
+    /// content may start with.
+    pub fn new(content: &'a str) -> Self {
         Options {
-            content,
+            content: content.strip_prefix('\u{feff}').unwrap_or(content),
             skip_commands: None,
+            deny: &[],
+            sudo_match: None,
+            tag: None,
+            group: None,
+            section: None,
+            execute_from: None,
+            execute_until: None,
+            execute_from_prefix: false,
+            execute_until_prefix: false,
+            skip_first: 0,
+            first_only: false,
+            shuffle: None,
+            languages: vec!["shell"],
+            prompts: vec!["$ "],
+            prompt_without_space: false,
+            prepend: &[],
+            append: &[],
+            parser_dump: None,
+            script: ScriptOptions::default(),
         }
     }
 
-    pub(crate) fn with_skip_commands(mut self, skip_commands: Option<&'a Regex>) -> Self {
+    pub fn with_skip_commands(mut self, skip_commands: Option<&'a Regex>) -> Self {
         self.skip_commands = skip_commands;
         self
     }
 
-    pub(crate) fn build(&'a self) -> CommandBlocks<'a> {
-        CommandBlocks::parse(self).expect("Failed to parse the MARKDOWN file")
+    /// Refuses to run any of the commands if one of them matches any of the given regular
+    /// expressions, such as `rm -rf /`, failing to build the commands instead.
+    pub fn with_deny(mut self, deny: &'a [Regex]) -> Self {
+        self.deny = deny;
+        self
+    }
+
+    /// Runs the commands matching the given regular expression with `sudo`, which is added before
+    /// each matching line of a code block, unless the line already starts with `sudo`.  A line
+    /// continued over the following lines with a backslash is matched as a whole, while comments
+    /// and the content of here-documents are never prefixed.  Ignored by the PowerShell script.
+    pub fn with_sudo_match(mut self, sudo_match: Option<&'a Regex>) -> Self {
+        self.sudo_match = sudo_match;
+        self
+    }
+
+    /// Only keeps the commands of the code blocks annotated with the given tag, using a
+    /// `# @tag <TAG>` comment line within the code block.  Fails if no code block has this tag.
+    pub fn with_tag(mut self, tag: Option<&'a str>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    /// Only keeps the commands of the code blocks of the given group, set with the `group`
+    /// attribute, such as ```` ```shell group=setup ````.  Fails if no code block has this group.
+    pub fn with_group(mut self, group: Option<&'a str>) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Only keeps the code blocks under the MARKDOWN heading with the given text, such as `Build`
+    /// for `## Build`, up to the next heading of the same or a higher level.  Fails if no heading
+    /// has this text.
+    pub fn with_section(mut self, section: Option<&'a str>) -> Self {
+        self.section = section;
+        self
+    }
+
+    /// Only keeps the code blocks starting from the one containing the given command, which may
+    /// be written with or without its prompt.  Fails if no code block contains this command.
+    pub fn with_execute_from(mut self, command: Option<&'a str>) -> Self {
+        self.execute_from = command;
+        self
+    }
+
+    /// Only keeps the code blocks up to, and including, the one containing the given command,
+    /// which may be written with or without its prompt.  Fails if no code block contains this
+    /// command.
+    pub fn with_execute_until(mut self, command: Option<&'a str>) -> Self {
+        self.execute_until = command;
+        self
+    }
+
+    /// Only keeps the code blocks starting from the one containing a command that starts with the
+    /// given prefix, such as `docker build`.  Replaces the command given to
+    /// [`Options::with_execute_from`], if any.
+    pub fn with_execute_from_prefix(mut self, prefix: Option<&'a str>) -> Self {
+        if prefix.is_some() {
+            self.execute_from = prefix;
+            self.execute_from_prefix = true;
+        }
+        self
+    }
+
+    /// Only keeps the code blocks up to, and including, the one containing a command that starts
+    /// with the given prefix.  Replaces the command given to [`Options::with_execute_until`], if
+    /// any.
+    pub fn with_execute_until_prefix(mut self, prefix: Option<&'a str>) -> Self {
+        if prefix.is_some() {
+            self.execute_until = prefix;
+            self.execute_until_prefix = true;
+        }
+        self
+    }
+
+    /// Drops the first given number of commands, such as prerequisites that were already run.
+    /// Applies after `execute_from`, `execute_until` and the tag, and fails if fewer commands are
+    /// found.
+    pub fn with_skip_first(mut self, skip_first: usize) -> Self {
+        self.skip_first = skip_first;
+        self
+    }
+
+    /// Only keeps the first command, such as a `--version` check used as a smoke test.  Applies
+    /// after `execute_from`, `execute_until`, the tag and `skip_first`, such that the first command
+    /// after these is kept.
+    pub fn with_first_only(mut self, first_only: bool) -> Self {
+        self.first_only = first_only;
+        self
+    }
+
+    /// Runs the commands in a random order, derived from the given seed, to find commands that
+    /// depend on the commands before them without saying so.  The same seed always gives the same
+    /// order.  Multi-line commands are moved as a whole, and so are the commands of the same
+    /// group, while the prepended and appended commands keep their place.  The shell script still
+    /// stops at the first failing command, as with `set -e`.
+    pub fn with_shuffle(mut self, seed: Option<u64>) -> Self {
+        self.shuffle = seed;
+        self
+    }
+
+    /// Sets the code block languages, such as `shell` and `bash`, whose commands are executed.
+    /// Commands are kept in the order these are found in the MARKDOWN content, irrespective of
+    /// their language.
+    pub fn with_languages(mut self, languages: &'a [String]) -> Self {
+        if !languages.is_empty() {
+            self.languages = languages.iter().map(|language| language.as_str()).collect();
+        }
+        self
+    }
+
+    /// Sets the prompts, such as `$ ` or `% `, which are removed from the start of the command
+    /// lines.  Tag annotations are recognised before the prompts are removed, so a `# ` prompt does
+    /// not affect them.
+    pub fn with_prompts(mut self, prompts: &'a [String]) -> Self {
+        self.prompts = prompts.iter().map(|prompt| prompt.as_str()).collect();
+        self
+    }
+
+    /// Also removes a `$` prompt written without its trailing space before a word starting with a
+    /// lowercase letter, such as `$echo hi`.  Off by default, as a variable expansion, such as
+    /// `$editor "$f"`, cannot be told apart from such a command and would lose its `$`.
+    pub fn with_prompt_without_space(mut self, prompt_without_space: bool) -> Self {
+        self.prompt_without_space = prompt_without_space;
+        self
+    }
+
+    /// Adds the given commands before the parsed commands, such as setup commands, which run like
+    /// any other command.
+    pub fn with_prepend(mut self, commands: &'a [String]) -> Self {
+        self.prepend = commands;
+        self
+    }
+
+    /// Adds the given commands after the parsed commands, such as teardown commands, which run
+    /// like any other command.
+    pub fn with_append(mut self, commands: &'a [String]) -> Self {
+        self.append = commands;
+        self
+    }
+
+    /// Developer tool: passes a description of the parser's state after each line of the MARKDOWN
+    /// content to the given function, such as whether the line is within a code block, the fence's
+    /// offset, the open here-documents and whether the line completed a command.  The format may
+    /// change at any time.  Not used when parsing a list of commands.
+    #[doc(hidden)]
+    pub fn with_parser_dump(mut self, dump: Option<fn(&str)>) -> Self {
+        self.parser_dump = dump;
+        self
+    }
+
+    /// Removes the prompt from the start of the line, ignoring any whitespace before the prompt.
+    /// Lines without a prompt are returned as is, keeping their indentation.
+    fn strip_prompt<'l>(&self, line: &'l str) -> &'l str {
+        let trimmed = line.trim_start();
+        self.prompts
+            .iter()
+            .find_map(|prompt| {
+                trimmed
+                    .strip_prefix(prompt)
+                    .or_else(|| self.strip_prompt_without_space(line, prompt))
+            })
+            .unwrap_or(line)
+    }
+
+    /// Removes a `$` prompt written without its trailing space, at the very start of the line,
+    /// when what follows is a path, such as `$./gradlew build`, which cannot be a variable.  A
+    /// word starting with a lowercase letter, such as `$echo hi`, is only a command when enabled
+    /// with [`Options::with_prompt_without_space`], and never when it is a variable assignment,
+    /// such as `$var=1`.
+    fn strip_prompt_without_space<'l>(&self, line: &'l str, prompt: &str) -> Option<&'l str> {
+        let bare = prompt.trim_end();
+        if bare == prompt || !bare.ends_with('$') {
+            return None;
+        }
+
+        line.strip_prefix(bare).filter(|rest| {
+            let word = rest.split_whitespace().next().unwrap_or_default();
+            let lowercase =
+                self.prompt_without_space && rest.starts_with(|c: char| c.is_ascii_lowercase());
+            (lowercase || rest.starts_with(['.', '/'])) && !word.contains('=')
+        })
+    }
+
+    /// Returns `true` if the given line is the given command, or starts with it when matching a
+    /// prefix, comparing both the line as written in the MARKDOWN file and the line without its
+    /// prompt, ignoring surrounding whitespace.
+    fn is_command(&self, line: &str, command: Option<&str>, prefix: bool) -> bool {
+        command.is_some_and(|command| {
+            let command = command.trim();
+            [line.trim(), self.strip_prompt(line).trim()]
+                .into_iter()
+                .any(|line| {
+                    if prefix {
+                        line.starts_with(command)
+                    } else {
+                        line == command
+                    }
+                })
+        })
+    }
+
+    /// Runs all commands the given number of times, one iteration after the other, printing how
+    /// long each iteration took.
+    pub fn with_repeat(mut self, repeat: usize) -> Self {
+        self.script.repeat = repeat;
+        self
+    }
+
+    /// Only shows the output of the failing command, hiding the output of the commands that
+    /// succeed.
+    pub fn with_quiet_success(mut self, quiet_success: bool) -> Self {
+        self.script.quiet_success = quiet_success;
+        self
+    }
+
+    /// Fails the commands that take longer than the given number of seconds to complete.
+    pub fn with_timeout(mut self, timeout: Option<u32>) -> Self {
+        self.script.timeout = timeout;
+        self
+    }
+
+    /// Precedes each command in the shell script with a `# command <N>` comment, which helps
+    /// relating the shell script to the MARKDOWN file.
+    pub fn with_annotate(mut self, annotate: bool) -> Self {
+        self.script.annotate = annotate;
+        self
+    }
+
+    /// Prints each command, with its expansions, as the shell runs it, which helps debugging the
+    /// generated shell script.
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.script.trace = trace;
+        self
+    }
+
+    /// Sets the first line of the shell script, such as `#!/usr/bin/env bash`, instead of
+    /// `#!/bin/sh`.  The `#!` is added when missing.
+    pub fn with_shebang(mut self, shebang: Option<&str>) -> Self {
+        self.script.shebang = shebang.map(|shebang| {
+            if shebang.starts_with("#!") {
+                shebang.to_string()
+            } else {
+                format!("#!{}", shebang)
+            }
+        });
+        self
+    }
+
+    /// Starts the script with the shebang, a comment and `set -e`, the default, or with the
+    /// commands themselves when `false` is given, for commands that set their own shell options.
+    /// A shell script without a preamble runs all commands, even when some fail, and is run by
+    /// `/bin/sh` unless another shell is set.  The strict and trace options still apply.
+    pub fn with_preamble(mut self, preamble: bool) -> Self {
+        self.script.preamble = preamble;
+        self
+    }
+
+    /// Prints the comment lines of the commands, starting with `# `, when these are reached while
+    /// running the commands, such as to narrate the steps of a tutorial, instead of leaving them
+    /// as inert shell comments.  The comments within here-documents, or following a line ending
+    /// with a backslash, are left as is.  Ignored by the PowerShell script.
+    pub fn with_echo_comments(mut self, echo_comments: bool) -> Self {
+        self.script.echo_comments = echo_comments;
+        self
+    }
+
+    /// Reports the result of each command in the Test Anything Protocol (TAP) format, written to
+    /// the standard output, while the output of the commands is written to the standard error.
+    /// All commands run, even when some fail, and the shell script fails if any command failed.
+    /// Each command runs in a subshell, which stops at the command's first failing line, so its
+    /// changes to the current directory or to variables are not seen by the following commands.
+    pub fn with_tap(mut self, tap: bool) -> Self {
+        self.script.tap = tap;
+        self
+    }
+
+    /// Fails on undefined variables with `set -u`, and, when the shebang is for `bash` or `zsh`,
+    /// on any failing command within a pipeline with `set -o pipefail`.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.script.strict = strict;
+        self
+    }
+
+    /// Runs each command in its own subshell, such that changes to the current directory or to
+    /// variables made by one command are not seen by the following commands.
+    pub fn with_isolate(mut self, isolate: bool) -> Self {
+        self.script.isolate = isolate;
+        self
+    }
+
+    /// Makes the shell script delete itself when it exits, even when the process running it is
+    /// killed before deleting it.  Only used for temporary shell scripts.
+    pub fn with_self_delete(mut self, self_delete: bool) -> Self {
+        self.script.self_delete = self_delete;
+        self
+    }
+
+    /// Sets the line printed before each command, which is `---` by default, or prints no line
+    /// between commands when `None` is given.
+    pub fn with_separator(mut self, separator: Option<&str>) -> Self {
+        self.script.separator = separator.map(|separator| separator.to_string());
+        self
+    }
+
+    /// Precedes each command's output, on both the standard output and error, with a marker line
+    /// holding the command's number, which is used to prefix the output of each command while
+    /// relaying it when running the commands.
+    pub fn with_prefix_output(mut self, prefix_output: bool) -> Self {
+        self.script.prefix_output = prefix_output;
+        self
+    }
+
+    /// Sets how each command is executed by the generated shell script.
+    pub fn with_execution_mode(mut self, execution_mode: ExecutionMode) -> Self {
+        self.script.execution_mode = execution_mode;
+        self
+    }
+
+    /// Returns the opening fence of a code block in any language, together with its language,
+    /// which is empty when the fence has no info string, and the rest of the info string.  The
+    /// fence must be the first text on the line, after the indentation and the blockquote markers.
+    fn find_opening_fence<'l>(&self, line: &'l str) -> Option<(Fence, &'l str, &'l str)> {
+        let offset = [line.find("```"), line.find("~~~")]
+            .into_iter()
+            .flatten()
+            .min()?;
+        let character = line[offset..].chars().next()?;
+        let length = line[offset..]
+            .chars()
+            .take_while(|c| *c == character)
+            .count();
+        let info = line[offset + length..].trim_start();
+
+        /* The info string of a backtick fence cannot contain backticks, such as an inline code span */
+        if character == '`' && info.contains('`') {
+            return None;
+        }
+
+        /* A fence is only preceded by its indentation, the quote markers found on each of its lines
+         * within a blockquote and the marker of the list item it opens, unlike a fence mentioned in prose */
+        let prefix = &line[..offset];
+        let is_marker = |token: &str| {
+            token.chars().all(|c| c == '>')
+                || matches!(token, "-" | "*" | "+")
+                || token.strip_suffix(['.', ')']).is_some_and(|number| {
+                    (1..=9).contains(&number.len()) && number.chars().all(|c| c.is_ascii_digit())
+                })
+        };
+        if !(prefix.split_whitespace().all(is_marker)
+            && (prefix.is_empty() || prefix.ends_with(|c: char| c == '>' || c.is_whitespace())))
+        {
+            return None;
+        }
+        let quotes = prefix.matches('>').count();
+        let mut fence = Fence {
+            offset: 0,
+            quotes,
+            character,
+            length,
+        };
+        fence.offset = offset - (line.len() - fence.strip_quotes(line).len());
+
+        let language = info.split_whitespace().next().unwrap_or_default();
+        Some((fence, language, &info[language.len()..]))
+    }
+
+    /// Parses the commands from the shell code blocks of the MARKDOWN content.  Panics if the
+    /// content cannot be parsed, such as when a code block is not closed.
+    pub fn build(&'a self) -> CommandBlocks<'a> {
+        self.try_build().expect("Failed to parse the MARKDOWN file")
+    }
+
+    /// Parses the commands from the shell code blocks of the MARKDOWN content, like
+    /// [`Options::build`], returning an error when the content cannot be parsed instead.
+    pub fn try_build(&'a self) -> Result<CommandBlocks<'a>, ParserError> {
+        CommandBlocks::parse(self)
+    }
+
+    /// Builds the commands from content written in the list format, as returned by
+    /// [`CommandBlocks::as_list`], instead of MARKDOWN.
+    pub fn build_from_list(&'a self) -> CommandBlocks<'a> {
+        self.try_build_from_list()
+            .expect("Failed to parse the list of commands")
+    }
+
+    /// Builds the commands from content written in the list format, like
+    /// [`Options::build_from_list`], returning an error when the content cannot be parsed instead.
+    pub fn try_build_from_list(&'a self) -> Result<CommandBlocks<'a>, ParserError> {
+        CommandBlocks::parse_list(self)
+    }
+}
+
+/// The opening fence of a code block, made of three or more backticks or tildes, which is closed
+/// by a fence of the same character that is at least as long.  The offset is the fence's column
+/// after removing the blockquote markers, if the code block is within a blockquote.
+#[derive(Debug, Clone, Copy)]
+struct Fence {
+    offset: usize,
+    quotes: usize,
+    character: char,
+    length: usize,
+}
+
+impl Fence {
+    fn is_closed_by(&self, line: &str) -> bool {
+        /* A closing fence may be followed by whitespace, but not by any other text */
+        let fence = self.content(line).trim_end();
+        fence.len() >= self.length && fence.chars().all(|c| c == self.character)
+    }
+
+    /// Returns the given line of the code block without the blockquote markers and the
+    /// indentation of the fence.
+    fn content<'l>(&self, line: &'l str) -> &'l str {
+        let line = self.strip_quotes(line);
+        if line.len() > self.offset {
+            &line[self.offset..]
+        } else {
+            ""
+        }
+    }
+
+    /// Removes the blockquote markers, each optionally followed by a space, such as `> `.
+    fn strip_quotes<'l>(&self, line: &'l str) -> &'l str {
+        (0..self.quotes).fold(line, |line, _| {
+            let trimmed = line.trim_start();
+            match trimmed.strip_prefix('>') {
+                Some(rest) => rest.strip_prefix(' ').unwrap_or(rest),
+                None => line,
+            }
+        })
+    }
+}
+
+/// The attributes of a code block, written within braces after the language of the opening fence,
+/// such as ```` ```shell {timeout=30} ````.  Attributes are separated by commas or spaces, and
+/// values containing spaces are written within double quotes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct BlockAttributes<'a> {
+    /// Skips the code block, set by the `skip` attribute, or by the `doc` and `ignore` attributes
+    /// for code blocks that only illustrate commands.  The `ignore` marker can also be written
+    /// without braces, such as ```` ```shell ignore ````
+    skip: bool,
+    /// Fails the code block's command if it takes longer than the given number of seconds,
+    /// overriding the `--timeout` option
+    timeout: Option<u32>,
+    /// Printed as a banner before the code block's command runs.  Can also be written without
+    /// braces, such as ```` ```shell title="Install step" ````
+    title: Option<&'a str>,
+    /// Keeps the commands of the code blocks of the same group together, such as those sharing
+    /// variables, which can be run on their own.  Can also be written without braces, such as
+    /// ```` ```shell group=setup ````
+    group: Option<&'a str>,
+    /// Expects the code block's command to fail, set by the `expect_fail` attribute, or to exit
+    /// with the given status, set by the `expect=<STATUS>` attribute.  The `expect_fail` marker
+    /// can also be written without braces, such as ```` ```shell expect_fail ````
+    expected_status: Option<ExpectedStatus>,
+}
+
+impl<'a> BlockAttributes<'a> {
+    fn parse(info: &'a str) -> Result<Self, ParserError> {
+        let mut attributes = BlockAttributes::default();
+
+        let info = info.trim();
+        let (bare, braced) = match Self::find_outside_quotes(info, '{') {
+            Some(index) => (&info[..index], Some(&info[index + 1..])),
+            None => (info, None),
+        };
+
+        /* Other words following the language are not attributes and are ignored */
+        for attribute in Self::split(bare)? {
+            match attribute.split_once('=') {
+                None if attribute == "ignore" => attributes.skip = true,
+                None if attribute == "expect_fail" => {
+                    attributes.expected_status = Some(ExpectedStatus::Failure)
+                }
+                Some(("title", title)) => attributes.title = Some(Self::unquote(title)),
+                Some(("group", group)) => attributes.group = Some(Self::unquote(group)),
+                _ => {}
+            }
+        }
+
+        let Some(braced) = braced else {
+            return Ok(attributes);
+        };
+        let Some(braced) = braced.trim_end().strip_suffix('}') else {
+            return ParserError::err(
+                "Missing closing brace in the code block attributes".to_string(),
+            );
+        };
+
+        for attribute in Self::split(braced)? {
+            match attribute.split_once('=') {
+                None if matches!(attribute, "skip" | "doc" | "ignore") => attributes.skip = true,
+                Some(("timeout", seconds)) => {
+                    let seconds = seconds.parse().map_err(|_| {
+                        ParserError::new(format!("Invalid timeout attribute: {}", attribute))
+                    })?;
+                    attributes.timeout = Some(seconds);
+                }
+                Some(("title", title)) => attributes.title = Some(Self::unquote(title)),
+                Some(("group", group)) => attributes.group = Some(Self::unquote(group)),
+                None if attribute == "expect_fail" => {
+                    attributes.expected_status = Some(ExpectedStatus::Failure)
+                }
+                Some(("expect", status)) => {
+                    attributes.expected_status =
+                        Some(ExpectedStatus::parse(status).ok_or_else(|| {
+                            ParserError::new(format!("Invalid expect attribute: {}", attribute))
+                        })?);
+                }
+                _ => {
+                    return ParserError::err(format!("Unknown code block attribute: {}", attribute))
+                }
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Splits the attributes separated by commas or spaces, which are kept within double quotes.
+    fn split(info: &'a str) -> Result<Vec<&'a str>, ParserError> {
+        let mut attributes = vec![];
+        let mut start = 0;
+        let mut quoted = false;
+
+        for (index, character) in info.char_indices() {
+            match character {
+                '"' => quoted = !quoted,
+                ',' | ' ' | '\t' if !quoted => {
+                    attributes.push(&info[start..index]);
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+
+        if quoted {
+            return ParserError::err(
+                "Missing closing quote in the code block attributes".to_string(),
+            );
+        }
+        attributes.push(&info[start..]);
+        attributes.retain(|attribute| !attribute.is_empty());
+        Ok(attributes)
+    }
+
+    fn find_outside_quotes(info: &str, needle: char) -> Option<usize> {
+        let mut quoted = false;
+        info.char_indices().find_map(|(index, character)| {
+            if character == '"' {
+                quoted = !quoted;
+            }
+            (!quoted && character == needle).then_some(index)
+        })
+    }
+
+    fn unquote(value: &str) -> &str {
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value)
+    }
+}
+
+/// The exit status a command is expected to have, when the command demonstrates a failure.  The
+/// shell script fails if the command exits with any other status, including when it succeeds.
+/// Such a command runs in a subshell, so its changes to the current directory or to variables are
+/// not seen by the following commands.  Ignored by the PowerShell script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedStatus {
+    /// Any non-zero exit status
+    Failure,
+    /// The given exit status
+    Status(u8),
+}
+
+impl ExpectedStatus {
+    fn parse(status: &str) -> Option<Self> {
+        status.trim().parse().ok().map(ExpectedStatus::Status)
     }
 }
 
+/// Starts the marker line printed before each command's output when the output is prefixed, and
+/// which is followed by the command's number.  The record separator character is used as it is
+/// unlikely to be printed by the commands themselves.
+pub(crate) const COMMAND_MARKER: char = '\u{1e}';
+
+/// How each command is executed by the generated shell script.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Runs each command once, stopping at the first failing command
+    #[default]
+    Default,
+    /// Runs each failing command again, up to the given number of attempts, doubling the delay
+    /// (in milliseconds) between one attempt and the next.  Each attempt runs in a subshell, which
+    /// stops at the command's first failing line, so its changes to the current directory or to
+    /// variables are not seen by the following commands
+    RetryWithBackoff { attempts: u32, delay_ms: u32 },
+    /// Runs each command once, waiting the given number of milliseconds between one command and
+    /// the next
+    DelayBetweenCommands { delay_ms: u32 },
+    /// Runs the commands in the background, in batches of up to the given number of jobs, waiting
+    /// for each batch to complete before starting the next.  Each command runs in its own
+    /// subshell, which stops at the command's first failing line as usual, but a failing command
+    /// does not stop the other commands of its batch.  The shell script fails once the batch
+    /// completes if any of its commands failed.
+    Parallel { jobs: usize },
+}
+
+/// Controls how the shell script is generated from the parsed commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScriptOptions {
+    repeat: usize,
+    execution_mode: ExecutionMode,
+    quiet_success: bool,
+    timeout: Option<u32>,
+    annotate: bool,
+    prefix_output: bool,
+    separator: Option<String>,
+    trace: bool,
+    self_delete: bool,
+    isolate: bool,
+    shebang: Option<String>,
+    preamble: bool,
+    echo_comments: bool,
+    tap: bool,
+    strict: bool,
+}
+
+impl Default for ScriptOptions {
+    fn default() -> Self {
+        ScriptOptions {
+            repeat: 1,
+            execution_mode: ExecutionMode::Default,
+            quiet_success: false,
+            timeout: None,
+            annotate: false,
+            prefix_output: false,
+            separator: Some("---".to_string()),
+            trace: false,
+            self_delete: false,
+            isolate: false,
+            shebang: None,
+            preamble: true,
+            echo_comments: false,
+            tap: false,
+            strict: false,
+        }
+    }
+}
+
+/// The error returned when the commands cannot be parsed from the content, such as when a code
+/// block is not closed, or are refused, holding the line on which the error was found, if known.
 #[derive(Debug, PartialEq, Eq)]
-struct ParserError {
+pub struct ParserError {
     message: String,
+    line_number: Option<usize>,
 }
 
 impl ParserError {
     fn new(message: String) -> Self {
-        ParserError { message }
+        ParserError {
+            message,
+            line_number: None,
+        }
     }
 
     fn err<R>(message: String) -> Result<R, ParserError> {
         Err(Self::new(message))
     }
+
+    fn at_line(mut self, line_number: Option<usize>) -> Self {
+        self.line_number = line_number;
+        self
+    }
+
+    /// Returns the message describing the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the line, starting from 1, on which the error was found, if known.
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
 }
 
 impl Display for ParserError {
@@ -49,520 +761,3856 @@ impl Display for ParserError {
 
 impl std::error::Error for ParserError {}
 
+/// The command made of all lines of a shell code block.  The lines are kept as written, such that
+/// the shell decides whether a line ending with a backslash continues on the next line, and a
+/// backslash within quotes, such as in `echo 'a\'`, does not join two commands.  Blank lines are
+/// kept too, as these are part of a here-document's content, while a blank line following a line
+/// ending with a backslash ends the command, like it does in the shell.
 #[derive(Debug, PartialEq, Eq)]
-pub(crate) struct CommandBlock<'a> {
+pub struct CommandBlock<'a> {
     lines: Vec<&'a str>,
+    tag: Option<&'a str>,
+    line_number: Option<usize>,
+    timeout: Option<u32>,
+    title: Option<&'a str>,
+    group: Option<&'a str>,
+    expected_status: Option<ExpectedStatus>,
+    sudo: Vec<usize>,
 }
 
-impl<'a> Display for CommandBlock<'a> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut lines = self.lines.iter();
+impl<'a> CommandBlock<'a> {
+    fn new(lines: Vec<&'a str>) -> Self {
+        CommandBlock {
+            lines,
+            tag: None,
+            line_number: None,
+            timeout: None,
+            title: None,
+            group: None,
+            expected_status: None,
+            sudo: vec![],
+        }
+    }
 
-        if let Some(first_line) = lines.next() {
-            write!(f, "{}", first_line)?;
+    /// Returns the command's lines joined by new lines, as run by the shell.
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
 
-            for line in lines {
-                write!(f, "\n{}", line)?;
+    /// Returns the command's lines, without their prompt.
+    pub fn lines(&self) -> &[&'a str] {
+        &self.lines
+    }
+
+    /// Returns `true` if the command spans more than one line.
+    pub fn is_multiline(&self) -> bool {
+        self.lines.len() > 1
+    }
+
+    /// Returns the program the command runs, which is the first word of its first line, such as
+    /// `rm` for `rm -rf target`.
+    pub fn program(&self) -> Option<&'a str> {
+        self.lines
+            .first()
+            .and_then(|line| line.split_whitespace().next())
+    }
+
+    /// Returns the tag of the code block this command was read from, if any.
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
+
+    /// Returns the title of the code block this command was read from, if any.
+    pub fn title(&self) -> Option<&'a str> {
+        self.title
+    }
+
+    /// Returns the group of the code block this command was read from, if any.
+    pub fn group(&self) -> Option<&'a str> {
+        self.group
+    }
+
+    /// Returns the exit status the command is expected to have, set with the `expect_fail` or
+    /// `expect` code block attributes, or with a `# expect: <STATUS>` line, if any.
+    pub fn expected_status(&self) -> Option<ExpectedStatus> {
+        self.expected_status
+    }
+
+    /// Returns `true` if any line of the command is run with `sudo`, as it matches the regular
+    /// expression given to [`Options::with_sudo_match`].
+    pub fn is_sudo(&self) -> bool {
+        !self.sudo.is_empty()
+    }
+
+    /// Returns the index of each line starting a shell command, with the command's text, which
+    /// includes the following lines it continues on with a backslash, skipping the content of
+    /// here-documents.
+    fn find_shell_commands(&self) -> Vec<(usize, String)> {
+        let mut commands: Vec<(usize, String)> = vec![];
+        let mut here_document_delimiters = VecDeque::new();
+        let mut continued = false;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            if let Some(delimiter) = here_document_delimiters.front() {
+                if line.trim() == *delimiter {
+                    here_document_delimiters.pop_front();
+                }
+                continue;
+            }
+
+            match commands.last_mut() {
+                Some((_, command)) if continued => {
+                    command.push('\n');
+                    command.push_str(line);
+                }
+                _ => commands.push((index, line.to_string())),
             }
+            here_document_delimiters.extend(Self::find_here_document_delimiters(line));
+            continued = line.ends_with('\\');
         }
+        commands
+    }
 
-        Ok(())
+    /// Returns the indices of the lines starting a shell command that matches the given regular
+    /// expression, other than comments and the commands already run with `sudo`.
+    fn find_sudo_lines(&self, regex: &Regex) -> Vec<usize> {
+        self.find_shell_commands()
+            .into_iter()
+            .filter(|(_, command)| {
+                let trimmed = command.trim_start();
+                !trimmed.is_empty()
+                    && !trimmed.starts_with('#')
+                    && trimmed.split_whitespace().next() != Some("sudo")
+                    && regex.is_match(trimmed)
+            })
+            .map(|(index, _)| index)
+            .collect()
     }
-}
 
-#[derive(Debug, PartialEq, Eq)]
-pub(crate) struct CommandBlocks<'a> {
-    /* TODO: Consider switching to a VecDeque given that we pop elements from the front when iterating. */
-    commands: Vec<CommandBlock<'a>>,
+    /// Returns the command's lines, where the lines run with `sudo` are prefixed with it, after
+    /// their indentation.
+    fn script_lines(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if self.sudo.contains(&index) {
+                    let trimmed = line.trim_start();
+                    let indentation = &line[..line.len() - trimmed.len()];
+                    format!("{indentation}sudo {trimmed}")
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the line, starting from 1, on which the command starts, or `None` for the commands
+    /// that were not read from the content, such as those prepended or appended.
+    pub fn line_number(&self) -> Option<usize> {
+        self.line_number
+    }
+
+    /// Returns the delimiters of the here-documents opened by the given line, in order, such as
+    /// `A` and `B` for `cat << A << 'B'`.  Here-strings, written with `<<<`, are not
+    /// here-documents, and neither are the `<<` found within quotes, comments or arithmetic, such
+    /// as `$((1 << 2))`, or that are not followed by a delimiter word.
+    fn find_here_document_delimiters(line: &str) -> Vec<&str> {
+        let mut delimiters = vec![];
+        let mut quote = None;
+        let mut arithmetic_depth = 0;
+        let mut previous = ' ';
+        let mut index = 0;
+        while let Some(c) = line[index..].chars().next() {
+            let rest = &line[index..];
+            let mut length = c.len_utf8();
+            match quote {
+                Some('"') if c == '\\' => length += Self::next_char_length(rest),
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '\\' => length += Self::next_char_length(rest),
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == '#' && previous.is_whitespace() && arithmetic_depth == 0 => break,
+                None if rest.starts_with("((") || rest.starts_with("$((") => {
+                    length = rest.find("((").unwrap_or_default() + 2;
+                    arithmetic_depth += 2;
+                }
+                None if arithmetic_depth > 0 => match c {
+                    '(' => arithmetic_depth += 1,
+                    ')' => arithmetic_depth -= 1,
+                    _ => {}
+                },
+                None if rest.starts_with("<<<") => {
+                    length = rest.len() - rest.trim_start_matches('<').len();
+                }
+                None if rest.starts_with("<<") => {
+                    length = 2;
+                    let delimiter = rest[2..].strip_prefix('-').unwrap_or(&rest[2..]);
+                    let delimiter = delimiter.trim_start();
+                    let delimiter = delimiter
+                        .strip_prefix(['\'', '"', '\\'])
+                        .unwrap_or(delimiter);
+                    if delimiter.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                        let end = delimiter
+                            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                            .unwrap_or(delimiter.len());
+                        delimiters.push(&delimiter[..end]);
+                    }
+                }
+                None => {}
+            }
+            previous = c;
+            index += length;
+        }
+        delimiters
+    }
+
+    /// Returns the length, in bytes, of the character following the first one of the given text,
+    /// such as the character escaped by a backslash, if any.
+    fn next_char_length(text: &str) -> usize {
+        text.chars().nth(1).map_or(0, char::len_utf8)
+    }
+
+    /// Returns the tag name if the given line is a `# @tag <TAG>` annotation, which may also be
+    /// preceded by the `$ ` prompt.
+    fn parse_tag(line: &str) -> Option<&str> {
+        let line = line.trim();
+        let line = line.strip_prefix("$ ").unwrap_or(line);
+        line.strip_prefix("# @tag ")
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+    }
+
+    /// Returns the command's lines, with `sudo` where needed, joined by new lines, where the
+    /// comment lines, starting with `# `, are replaced by a `printf` of the comment's text, keeping
+    /// the comments within here-documents or following a line ending with a backslash.
+    fn text_with_echoed_comments(&self) -> String {
+        let mut here_document_delimiters = VecDeque::new();
+        let mut continued = false;
+        let mut lines = vec![];
+
+        let script_lines = self.script_lines();
+        for line in &script_lines {
+            let echoed = match here_document_delimiters.front() {
+                Some(delimiter) => {
+                    if line.trim() == *delimiter {
+                        here_document_delimiters.pop_front();
+                    }
+                    None
+                }
+                None if continued => None,
+                None => {
+                    let trimmed = line.trim_start();
+                    let comment = trimmed.strip_prefix("# ").map(|comment| {
+                        let indentation = &line[..line.len() - trimmed.len()];
+                        format!(
+                            "{}printf '%s\\n' '{}'",
+                            indentation,
+                            comment.replace('\'', "'\\''")
+                        )
+                    });
+                    if comment.is_none() {
+                        here_document_delimiters.extend(Self::find_here_document_delimiters(line));
+                    }
+                    comment
+                }
+            };
+            continued = line.ends_with('\\');
+            lines.push(echoed.unwrap_or_else(|| line.to_string()));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Returns the expected exit status if the given line is a `# expect: <STATUS>` annotation,
+    /// which may also be preceded by the `$ ` prompt.  Other comments starting with `# expect:`,
+    /// such as `# expect: a warning`, are not annotations.
+    fn parse_expected_status(line: &str) -> Option<ExpectedStatus> {
+        let line = line.trim();
+        let line = line.strip_prefix("$ ").unwrap_or(line);
+        line.strip_prefix("# expect:")
+            .and_then(ExpectedStatus::parse)
+    }
+}
+
+impl<'a> Display for CommandBlock<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut lines = self.lines.iter();
+
+        if let Some(first_line) = lines.next() {
+            write!(f, "{}", first_line)?;
+
+            for line in lines {
+                write!(f, "\n{}", line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The representation of a command returned by [`CommandBlocks::as_json`].
+#[derive(Debug, Serialize)]
+struct JsonCommand<'a> {
+    command: String,
+    lines: &'a [&'a str],
+    tag: Option<&'a str>,
+    line_number: Option<usize>,
+}
+
+/// The commands parsed from the MARKDOWN content, in the order these are found, which can be
+/// formatted as a shell script, a list or JSON.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandBlocks<'a> {
+    /* TODO: Consider switching to a VecDeque given that we pop elements from the front when iterating. */
+    commands: Vec<CommandBlock<'a>>,
+    script: ScriptOptions,
+    skipped: usize,
+    found: usize,
+    execute_from_line: Option<(usize, &'a str)>,
+    execute_until_line: Option<(usize, &'a str)>,
+}
+
+impl<'a> CommandBlocks<'a> {
+    /// Creates the parsed commands, preceded by the prepended commands and followed by the appended
+    /// commands.
+    fn new(options: &'a Options<'a>, commands: Vec<CommandBlock<'a>>, skipped: usize) -> Self {
+        let hook = |command: &'a String| CommandBlock::new(command.lines().collect());
+        let found = commands.len();
+        let commands = options
+            .prepend
+            .iter()
+            .map(hook)
+            .chain(commands)
+            .chain(options.append.iter().map(hook))
+            .map(|mut command| {
+                if let Some(regex) = options.sudo_match {
+                    command.sudo = command.find_sudo_lines(regex);
+                }
+                command
+            })
+            .collect();
+
+        CommandBlocks {
+            commands,
+            script: options.script.clone(),
+            skipped,
+            found,
+            execute_from_line: None,
+            execute_until_line: None,
+        }
+    }
+
+    fn parse(options: &'a Options<'a>) -> Result<Self, ParserError> {
+        let mut commands = vec![];
+        let mut buffered_commands = vec![];
+
+        let mut buffered_tag = None;
+        let mut buffered_line_number = None;
+        let mut buffered_timeout = None;
+        let mut buffered_title = None;
+        let mut buffered_group = None;
+        let mut buffered_expected_status = None;
+        let mut here_document_delimiters = VecDeque::new();
+
+        let mut within_command_block = None;
+        let mut within_other_block: Option<Fence> = None;
+        let mut skip_command_block = false;
+        let mut skipped = 0;
+
+        let mut section_level = None;
+        let mut section_found = false;
+
+        let mut started = options.execute_from.is_none();
+        let mut finished = false;
+        let mut execute_from_line = None;
+        let mut execute_until_line = None;
+        let mut block_from_line = None;
+        let mut block_until_line = None;
+
+        /* The state is dumped once the following line is reached, or the content ends */
+        let mut dumped = None;
+
+        for (index, line) in options.content.lines().enumerate() {
+            if let Some(dump) = options.parser_dump {
+                if let Some((dumped_index, dumped_line, dumped_found)) =
+                    dumped.replace((index, line, commands.len()))
+                {
+                    dump(&Self::describe_parser_state(
+                        dumped_index,
+                        dumped_line,
+                        commands.len() > dumped_found,
+                        within_command_block,
+                        within_other_block,
+                        skip_command_block,
+                        &here_document_delimiters,
+                    ));
+                }
+            }
+
+            /* The content of code blocks in other languages is ignored up to their closing fence */
+            if let Some(fence) = within_other_block {
+                if fence.is_closed_by(line) {
+                    within_other_block = None;
+                }
+                continue;
+            }
+
+            if let (None, Some(section), Some((level, heading))) = (
+                within_command_block,
+                options.section,
+                Self::parse_heading(line),
+            ) {
+                if heading == section {
+                    section_level = Some(level);
+                    section_found = true;
+                } else if section_level.is_some_and(|section_level| level <= section_level) {
+                    section_level = None;
+                }
+                continue;
+            }
+
+            if let Some((fence, language, info)) = options.find_opening_fence(line) {
+                match within_command_block {
+                    None if !options.languages.contains(&language) => {
+                        within_other_block = Some(fence);
+                        continue;
+                    }
+                    None => {
+                        let attributes = BlockAttributes::parse(info).map_err(|e| {
+                            ParserError::new(format!("{} on line {}", e.message, index + 1))
+                                .at_line(Some(index + 1))
+                        })?;
+                        skip_command_block = attributes.skip;
+                        buffered_timeout = attributes.timeout;
+                        buffered_title = attributes.title;
+                        buffered_group = attributes.group;
+                        buffered_expected_status = attributes.expected_status;
+                        within_command_block = Some(fence);
+                        continue;
+                    }
+                    Some(Fence {
+                        character, length, ..
+                    }) if options.languages.contains(&language)
+                        && fence.character == character
+                        && fence.length >= length =>
+                    {
+                        return Err(ParserError::new(
+                            "Nested code block are not supported".to_string(),
+                        )
+                        .at_line(Some(index + 1)));
+                    }
+                    /* A shorter fence is part of the code block, such as a MARKDOWN example */
+                    Some(_) => {}
+                }
+            }
+
+            if let Some(fence) = within_command_block {
+                if fence.is_closed_by(line) {
+                    if !started {
+                        execute_from_line = block_from_line;
+                    }
+                    started |= block_from_line.is_some();
+                    let in_section = options.section.is_none() || section_level.is_some();
+                    if !skip_command_block && started && in_section {
+                        let mut command = CommandBlock::new(buffered_commands);
+                        command.tag = buffered_tag.take();
+                        command.line_number = buffered_line_number;
+                        command.timeout = buffered_timeout;
+                        command.title = buffered_title;
+                        command.group = buffered_group;
+                        command.expected_status = buffered_expected_status;
+                        commands.push(command);
+                    }
+
+                    buffered_commands = vec![];
+                    buffered_tag = None;
+                    buffered_line_number = None;
+                    buffered_timeout = None;
+                    buffered_title = None;
+                    buffered_group = None;
+                    buffered_expected_status = None;
+                    here_document_delimiters.clear();
+                    within_command_block = None;
+                    skip_command_block = false;
+                    block_from_line = None;
+
+                    /* Ignore the rest of the content once the last command is found */
+                    if started && block_until_line.is_some() {
+                        execute_until_line = block_until_line;
+                        finished = true;
+                        break;
+                    }
+                    block_until_line = None;
+                    continue;
+                }
+            }
+
+            if skip_command_block {
+                continue;
+            }
+
+            if let Some(fence) = within_command_block {
+                let command_line = fence.content(line);
+
+                /* The lines of a here-document, up to its delimiter, are its content and not commands */
+                let within_here_document = !here_document_delimiters.is_empty();
+
+                /* The shell only closes a here-document when its delimiter is alone on the line, and
+                 * the here-documents opened by the same line are closed in order */
+                let command_line = match here_document_delimiters.front() {
+                    Some(delimiter) if command_line.trim() == *delimiter => {
+                        here_document_delimiters.pop_front();
+                        command_line.trim()
+                    }
+                    Some(_) => command_line,
+                    None => {
+                        here_document_delimiters
+                            .extend(CommandBlock::find_here_document_delimiters(command_line));
+                        command_line
+                    }
+                };
+
+                if let (false, Some(tag)) =
+                    (within_here_document, CommandBlock::parse_tag(command_line))
+                {
+                    buffered_tag = Some(tag);
+                    continue;
+                }
+
+                if let (false, Some(status)) = (
+                    within_here_document,
+                    CommandBlock::parse_expected_status(command_line),
+                ) {
+                    buffered_expected_status = Some(status);
+                    continue;
+                }
+
+                /* Only the first matching line of the code block is recorded */
+                if block_from_line.is_none()
+                    && options.is_command(
+                        command_line,
+                        options.execute_from,
+                        options.execute_from_prefix,
+                    )
+                {
+                    block_from_line = Some((index + 1, command_line.trim()));
+                }
+                if block_until_line.is_none()
+                    && options.is_command(
+                        command_line,
+                        options.execute_until,
+                        options.execute_until_prefix,
+                    )
+                {
+                    block_until_line = Some((index + 1, command_line.trim()));
+                }
+                if buffered_commands.is_empty() {
+                    buffered_line_number = Some(index + 1);
+                }
+                buffered_commands.push(if within_here_document {
+                    command_line
+                } else {
+                    options.strip_prompt(command_line)
+                });
+
+                /* Check if the command needs to be skipped and clear the buffer if so */
+                if let Some(regex) = &options.skip_commands {
+                    if regex.is_match(&buffered_commands.join(" ")) {
+                        skip_command_block = true;
+                        skipped += 1;
+                        buffered_commands.clear();
+                        continue;
+                    }
+                }
+            }
+        }
+
+        if let (Some(dump), Some((dumped_index, dumped_line, dumped_found))) =
+            (options.parser_dump, dumped)
+        {
+            dump(&Self::describe_parser_state(
+                dumped_index,
+                dumped_line,
+                commands.len() > dumped_found,
+                within_command_block,
+                within_other_block,
+                skip_command_block,
+                &here_document_delimiters,
+            ));
+        }
+
+        if let (false, Some(line_number)) = (buffered_commands.is_empty(), buffered_line_number) {
+            return Err(ParserError::new(format!(
+                "Failed to find closing code block for the command on line {}",
+                line_number
+            ))
+            .at_line(Some(line_number)));
+        }
+
+        if let (Some(section), false) = (options.section, section_found) {
+            return ParserError::err(format!("Unknown section: {}", section));
+        }
+
+        if let (Some(command), false) = (options.execute_from, started) {
+            return ParserError::err(format!("Unknown command to execute from: {}", command));
+        }
+
+        if let (Some(command), false) = (options.execute_until, finished) {
+            return ParserError::err(format!("Unknown command to execute until: {}", command));
+        }
+
+        Self::keep_groups_together(&mut commands);
+        if let Some(group) = options.group {
+            commands.retain(|command| command.group == Some(group));
+            if commands.is_empty() {
+                return ParserError::err(format!("Unknown group: {}", group));
+            }
+        }
+
+        if let Some(tag) = options.tag {
+            commands.retain(|command| command.tag == Some(tag));
+            if commands.is_empty() {
+                return ParserError::err(format!("Unknown tag: {}", tag));
+            }
+        }
+
+        Self::skip_first(options, &mut commands)?;
+        if options.first_only {
+            commands.truncate(1);
+        }
+        Self::deny(options, &commands)?;
+        Self::shuffle(options, &mut commands);
+        let mut commands = CommandBlocks::new(options, commands, skipped);
+        commands.execute_from_line = execute_from_line;
+        commands.execute_until_line = execute_until_line;
+        Ok(commands)
+    }
+
+    /// Describes the parser's state after the given line, such as
+    /// `   12 | command block | offset 2 | here-documents EOF | command | EOF`, for the parser
+    /// dump.
+    #[allow(clippy::too_many_arguments)]
+    fn describe_parser_state(
+        index: usize,
+        line: &str,
+        produced_command: bool,
+        within_command_block: Option<Fence>,
+        within_other_block: Option<Fence>,
+        skip_command_block: bool,
+        here_document_delimiters: &VecDeque<&str>,
+    ) -> String {
+        let (state, fence) = match (within_command_block, within_other_block) {
+            (Some(fence), _) if skip_command_block => ("skipped block", Some(fence)),
+            (Some(fence), _) => ("command block", Some(fence)),
+            (None, Some(fence)) => ("other block", Some(fence)),
+            (None, None) => ("text", None),
+        };
+        let offset = fence.map_or_else(|| "-".to_string(), |fence| fence.offset.to_string());
+        let delimiters = if here_document_delimiters.is_empty() {
+            "-".to_string()
+        } else {
+            Vec::from_iter(here_document_delimiters.iter().copied()).join(" ")
+        };
+        let produced = if produced_command { "command" } else { "-" };
+        format!(
+            "{:>5} | {:<13} | offset {} | here-documents {} | {} | {}",
+            index + 1,
+            state,
+            offset,
+            delimiters,
+            produced,
+            line
+        )
+    }
+
+    /// Returns the level and the text of an ATX heading, such as `2` and `Build` for `## Build`,
+    /// ignoring the optional closing sequence of `#`.
+    fn parse_heading(line: &str) -> Option<(usize, &str)> {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > 3 {
+            return None;
+        }
+
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        let text = &trimmed[level..];
+        if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+            return None;
+        }
+
+        let text = text.trim();
+        let text = match text.trim_end_matches('#') {
+            rest if rest.is_empty() => rest,
+            rest if rest.ends_with([' ', '\t']) => rest.trim_end(),
+            _ => text,
+        };
+        Some((level, text))
+    }
+
+    /// Moves the commands of each group right after the first command of their group, keeping
+    /// the order in which these are found otherwise.
+    fn keep_groups_together(commands: &mut Vec<CommandBlock<'a>>) {
+        let firsts: Vec<usize> = commands
+            .iter()
+            .enumerate()
+            .map(|(index, command)| {
+                command
+                    .group
+                    .and_then(|group| commands.iter().position(|c| c.group == Some(group)))
+                    .unwrap_or(index)
+            })
+            .collect();
+
+        let mut grouped: Vec<(usize, CommandBlock<'a>)> =
+            firsts.into_iter().zip(commands.drain(..)).collect();
+        grouped.sort_by_key(|(first, _)| *first);
+        commands.extend(grouped.into_iter().map(|(_, command)| command));
+    }
+
+    /// Shuffles the commands using the Fisher-Yates algorithm, driven by a SplitMix64 generator
+    /// seeded with the shuffle seed, if any.  The commands of the same group, which are already
+    /// next to each other, are shuffled as one.
+    fn shuffle(options: &Options<'a>, commands: &mut Vec<CommandBlock<'a>>) {
+        let Some(mut state) = options.shuffle else {
+            return;
+        };
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+
+        let mut units: Vec<Vec<CommandBlock<'a>>> = vec![];
+        for command in commands.drain(..) {
+            match units.last_mut() {
+                Some(unit) if command.group.is_some() && unit[0].group == command.group => {
+                    unit.push(command)
+                }
+                _ => units.push(vec![command]),
+            }
+        }
+
+        for index in (1..units.len()).rev() {
+            let other = (next() % (index as u64 + 1)) as usize;
+            units.swap(index, other);
+        }
+        commands.extend(units.into_iter().flatten());
+    }
+
+    fn skip_first(
+        options: &Options<'a>,
+        commands: &mut Vec<CommandBlock<'a>>,
+    ) -> Result<(), ParserError> {
+        if options.skip_first > commands.len() {
+            return ParserError::err(format!(
+                "Cannot skip the first {} commands as only {} were found",
+                options.skip_first,
+                commands.len()
+            ));
+        }
+
+        commands.drain(..options.skip_first);
+        Ok(())
+    }
+
+    fn deny(options: &Options<'a>, commands: &[CommandBlock<'a>]) -> Result<(), ParserError> {
+        for command in commands {
+            let text = command.text();
+            if let Some(regex) = options.deny.iter().find(|regex| regex.is_match(&text)) {
+                let line = command
+                    .line_number
+                    .map(|line_number| format!(" on line {}", line_number))
+                    .unwrap_or_default();
+                return Err(ParserError::new(format!(
+                    "Refusing to run the command{}, which matches the deny pattern '{}': {}",
+                    line, regex, text
+                ))
+                .at_line(command.line_number));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_list(options: &'a Options<'a>) -> Result<Self, ParserError> {
+        let mut commands = vec![];
+        let mut buffered_commands = vec![];
+
+        let mut buffered_line_number = None;
+
+        for (index, line) in options.content.lines().enumerate() {
+            if let Some(continuation) = line.strip_prefix('\t') {
+                if buffered_commands.is_empty() {
+                    return Err(ParserError::new(format!(
+                        "Found a continuation line without a command: {}",
+                        continuation
+                    ))
+                    .at_line(Some(index + 1)));
+                }
+
+                buffered_commands.push(continuation);
+                continue;
+            }
+
+            if !buffered_commands.is_empty() {
+                let mut command = CommandBlock::new(buffered_commands);
+                command.line_number = buffered_line_number;
+                commands.push(command);
+                buffered_commands = vec![];
+            }
+
+            if !line.is_empty() {
+                buffered_line_number = Some(index + 1);
+                buffered_commands.push(line);
+            }
+        }
+
+        if !buffered_commands.is_empty() {
+            let mut command = CommandBlock::new(buffered_commands);
+            command.line_number = buffered_line_number;
+            commands.push(command);
+        }
+
+        Self::skip_first(options, &mut commands)?;
+        if options.first_only {
+            commands.truncate(1);
+        }
+        Self::deny(options, &commands)?;
+        Self::shuffle(options, &mut commands);
+        Ok(CommandBlocks::new(options, commands, 0))
+    }
+
+    /// Returns an iterator over the parsed commands, in the order these are found.
+    pub fn iter(&self) -> impl Iterator<Item = &CommandBlock<'a>> {
+        self.commands.iter()
+    }
+
+    /// Returns the number of commands, including the prepended and appended commands, unlike
+    /// [`CommandBlocks::found`].
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns `true` if there are no commands, not even prepended or appended ones.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Returns the number of code blocks skipped as their commands match the skip commands regex.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Returns the number of commands found in the content, without the prepended and appended
+    /// commands.
+    pub fn found(&self) -> usize {
+        self.found
+    }
+
+    /// Returns the line number and the text, as written in the MARKDOWN content, of the line that
+    /// matched the command to execute from.  Only the first matching line is used when more than
+    /// one line matches.
+    pub fn execute_from_line(&self) -> Option<(usize, &'a str)> {
+        self.execute_from_line
+    }
+
+    /// Returns the line number and the text, as written in the MARKDOWN content, of the line that
+    /// matched the command to execute until.  Only the first matching line found after the command
+    /// to execute from is used.
+    pub fn execute_until_line(&self) -> Option<(usize, &'a str)> {
+        self.execute_until_line
+    }
+
+    /// Returns the commands in the list format, where commands are separated by an empty line and
+    /// every other line of a multi-line command starts with a tab.  The list can be edited and then
+    /// read back using [`Options::build_from_list`].
+    pub fn as_list(&self) -> String {
+        let mut buffer = String::new();
+
+        for (index, command) in self.commands.iter().enumerate() {
+            if index > 0 {
+                buffer.push('\n');
+            }
+
+            buffer.push_str(&command.lines.join("\n\t"));
+            buffer.push('\n');
+        }
+
+        buffer
+    }
+
+    /// Returns the commands as a JSON array, where each command is an object holding the command's
+    /// text, its lines, the tag of its code block, if any, and the line on which it starts.
+    pub fn as_json(&self) -> String {
+        let commands: Vec<JsonCommand<'_>> = self
+            .iter()
+            .map(|command| JsonCommand {
+                command: command.text(),
+                lines: command.lines(),
+                tag: command.tag(),
+                line_number: command.line_number(),
+            })
+            .collect();
+        serde_json::to_string(&commands).expect("Failed to serialize the commands")
+    }
+
+    /// Returns the script that runs the commands on the current platform, which is a PowerShell
+    /// script on Windows and a shell script everywhere else.
+    pub fn as_script(&self) -> String {
+        if cfg!(windows) {
+            self.as_powershell_script()
+        } else {
+            self.as_shell_script()
+        }
+    }
+
+    pub fn as_shell_script(&self) -> String {
+        let mut buffer_command = String::new();
+        if self.script.preamble {
+            buffer_command.push_str(self.script.shebang.as_deref().unwrap_or("#!/bin/sh"));
+            buffer_command.push_str(
+                r#"
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+"#,
+            );
+        }
+
+        /* The pipefail option is not POSIX, and is only set for the shells known to support it */
+        if self.script.strict {
+            buffer_command.push_str("set -u\n");
+            let shebang = self.script.shebang.as_deref().unwrap_or_default();
+            if shebang.contains("bash") || shebang.contains("zsh") {
+                buffer_command.push_str("set -o pipefail\n");
+            }
+        }
+
+        if self.script.trace {
+            buffer_command.push_str("set -x\n");
+        }
+        if !buffer_command.is_empty() {
+            buffer_command.push('\n');
+        }
+
+        /* The shell script deletes itself on exit, in case this process is killed before it does */
+        let delete_script = if self.script.self_delete {
+            "rm -f \"$0\"; "
+        } else {
+            ""
+        };
+
+        /* Each command writes its output to a file, which is only shown if the command fails */
+        if self.script.quiet_success {
+            buffer_command.push_str(
+                format!(
+                    r#"me_output="$(mktemp)"
+exec 3>&1 4>&2
+trap 'me_status=$?; exec 1>&3 2>&4; [ "$me_status" -eq 0 ] || cat "$me_output"; rm -f "$me_output"; {delete_script}exit "$me_status"' EXIT
+
+"#
+                )
+                .as_str(),
+            );
+        } else if self.script.self_delete {
+            buffer_command.push_str("trap 'rm -f \"$0\"' EXIT\n\n");
+        }
+
+        if let ExecutionMode::RetryWithBackoff { .. } = self.script.execution_mode {
+            buffer_command.push_str(
+                r#"me_retry() {
+  me_attempts="$1"
+  me_delay_ms="$2"
+  me_attempt=1
+  while :; do
+    set +e
+    (
+      set -e
+      "$3"
+    )
+    me_status=$?
+    set -e
+    if [ "$me_status" -eq 0 ]; then
+      return 0
+    fi
+    if [ "$me_attempt" -ge "$me_attempts" ]; then
+      echo "Command failed after $me_attempt attempts" >&2
+      return 1
+    fi
+    echo "Attempt $me_attempt failed, retrying in $me_delay_ms milliseconds" >&2
+    sleep "$((me_delay_ms / 1000)).$(printf '%03d' "$((me_delay_ms % 1000))")"
+    me_attempt=$((me_attempt + 1))
+    me_delay_ms=$((me_delay_ms * 2))
+  done
+}
+
+"#,
+            );
+        }
+
+        /* Waits for all background commands, and fails if any of them failed */
+        if let ExecutionMode::Parallel { .. } = self.script.execution_mode {
+            buffer_command.push_str(
+                r#"me_pids=""
+me_wait() {
+  me_failed=0
+  for me_pid in $me_pids; do
+    wait "$me_pid" || me_failed=1
+  done
+  me_pids=""
+  return "$me_failed"
+}
+
+"#,
+            );
+        }
+
+        /* The test results are written to the standard output, and everything else to the standard error */
+        if self.script.tap {
+            buffer_command.push_str("exec 5>&1 1>&2\nme_test=0\nme_failed=0\n\n");
+        }
+
+        /* Relies on the timeout utility, and runs the commands without a time limit when missing */
+        if self.script.timeout.is_some() || self.commands.iter().any(|c| c.timeout.is_some()) {
+            /* The timed commands are run by the interpreter of the shebang, like the script itself */
+            let interpreter = self
+                .script
+                .shebang
+                .as_deref()
+                .and_then(|shebang| shebang.strip_prefix("#!"))
+                .map(str::trim)
+                .filter(|interpreter| !interpreter.is_empty())
+                .unwrap_or("/bin/sh");
+            buffer_command.push_str(
+                r#"me_timeout() {
+  if command -v timeout >/dev/null 2>&1; then
+    me_status=0
+    timeout "$1" "#,
+            );
+            buffer_command.push_str(interpreter);
+            buffer_command.push_str(
+                r#" -ec "$2" || me_status=$?
+    if [ "$me_status" -eq 124 ]; then
+      echo "Command timed out after $1 seconds" >&2
+    fi
+    return "$me_status"
+  fi
+
+  eval "$2"
+}
+
+if ! command -v timeout >/dev/null 2>&1; then
+  echo "The timeout utility is missing, running the commands without a timeout" >&2
+fi
+
+"#,
+            );
+        }
+
+        /* Times the iterations in milliseconds where `date` supports nanoseconds, unlike on macOS */
+        let repeat = self.script.repeat > 1;
+        if repeat {
+            buffer_command.push_str(
+                format!(
+                    r#"me_millis() {{
+  me_nanos="$(date +%s%N)"
+  case "$me_nanos" in
+    *[!0-9]*) echo "$(($(date +%s) * 1000))" ;;
+    *) echo "$((me_nanos / 1000000))" ;;
+  esac
+}}
+
+me_iteration=1
+while [ "$me_iteration" -le {} ]; do
+me_started="$(me_millis)"
+
+"#,
+                    self.script.repeat
+                )
+                .as_str(),
+            );
+        }
+
+        for (index, command) in self.commands.iter().enumerate() {
+            let first_line = command.lines.first().copied().unwrap_or_default();
+            let expected_status = command.expected_status;
+
+            if let (ExecutionMode::DelayBetweenCommands { delay_ms }, true) =
+                (self.script.execution_mode, index > 0)
+            {
+                buffer_command.push_str(
+                    format!("sleep {}.{:03}\n\n", delay_ms / 1000, delay_ms % 1000).as_str(),
+                );
+            }
+
+            if self.script.annotate {
+                buffer_command.push_str(format!("# command {}\n", index + 1).as_str());
+            }
+
+            if self.script.quiet_success {
+                buffer_command.push_str("exec >\"$me_output\" 2>&1\n");
+            }
+
+            if self.script.prefix_output {
+                buffer_command.push_str(
+                    format!(
+                        "printf '\\036%d\\n' {0}\nprintf '\\036%d\\n' {0} >&2\n",
+                        index + 1
+                    )
+                    .as_str(),
+                );
+            }
+
+            if let Some(separator) = &self.script.separator {
+                let escaped = separator.replace('\'', "'\\''");
+                buffer_command.push_str(format!("printf '%s\\n' '{escaped}'\n").as_str());
+            }
+
+            if let Some(title) = command.title {
+                let escaped = title.replace('\'', "'\\''");
+                buffer_command.push_str(format!("printf '%s\\n' '=== {escaped} ==='\n").as_str());
+            }
+
+            /* Printed as is, as `echo` may interpret the backslashes, depending on the shell */
+            let mut lines = command
+                .script_lines()
+                .into_iter()
+                .map(|line| str::replace(&line, "'", "'\\''"));
+            if let Some(first_line) = lines.next() {
+                if first_line.contains('$') {
+                    buffer_command.push_str("# shellcheck disable=SC2016\n");
+                }
+                buffer_command.push_str(format!("printf '%s\\n' '$ {first_line}'\n").as_str());
+
+                for line in lines {
+                    buffer_command.push_str(format!("printf '%s\\n' '> {line}'\n").as_str());
+                }
+            }
+
+            let timeout = command.timeout.or(self.script.timeout);
+            let command = if self.script.echo_comments {
+                command.text_with_echoed_comments()
+            } else {
+                command.script_lines().join("\n")
+            };
+            let command = match timeout {
+                Some(seconds) => {
+                    let escaped = command.replace('\'', "'\\''");
+                    format!("me_timeout {seconds} '{escaped}'")
+                }
+                None => command,
+            };
+            let command = if self.script.isolate {
+                format!("(\n{command}\n)")
+            } else {
+                command
+            };
+
+            /* The command runs in a subshell, which stops at its first failing line, without
+             * stopping the shell script, and the shell script fails if the status is not the expected one */
+            let command = match expected_status {
+                None => command,
+                Some(expected) => {
+                    let (unexpected, message) = match expected {
+                        ExpectedStatus::Failure => (
+                            r#"[ "$me_status" -eq 0 ]"#.to_string(),
+                            "Expected the command to fail, but it succeeded".to_string(),
+                        ),
+                        ExpectedStatus::Status(status) => (
+                            format!(r#"[ "$me_status" -ne {status} ]"#),
+                            format!(
+                                "Expected the command to exit with {status}, but it exited with $me_status"
+                            ),
+                        ),
+                    };
+                    format!(
+                        r#"set +e
+(
+set -e
+{command}
+)
+me_status=$?
+set -e
+if {unexpected}; then
+  echo "{message}" >&2
+  false
+fi"#
+                    )
+                }
+            };
+
+            let command = match self.script.execution_mode {
+                ExecutionMode::Default | ExecutionMode::DelayBetweenCommands { .. } => command,
+                ExecutionMode::RetryWithBackoff { attempts, delay_ms } => {
+                    format!(
+                        "me_command() {{\n{command}\n}}\nme_retry {attempts} {delay_ms} me_command"
+                    )
+                }
+                ExecutionMode::Parallel { jobs } => {
+                    let batch_completed =
+                        (index + 1) % jobs.max(1) == 0 || index + 1 == self.commands.len();
+                    let wait = if batch_completed { "\nme_wait" } else { "" };
+                    format!("(\n{command}\n) &\nme_pids=\"$me_pids $!\"{wait}")
+                }
+            };
+
+            /* Reports the result of the command, and runs the following commands even if it fails.  The
+             * command runs in a subshell as `set -e` does not apply within the condition of an `if` */
+            let command = if self.script.tap {
+                /* The backslashes and `#` are escaped as in TAP descriptions, and printed as is */
+                let description = first_line
+                    .replace('\\', "\\\\")
+                    .replace('#', "\\#")
+                    .replace('\'', "'\\''");
+                format!(
+                    r#"me_test=$((me_test + 1))
+set +e
+(
+set -e
+{command}
+)
+me_status=$?
+set -e
+if [ "$me_status" -eq 0 ]; then
+  printf 'ok %d - %s\n' "$me_test" '{description}' >&5
+else
+  printf 'not ok %d - %s\n' "$me_test" '{description}' >&5
+  me_failed=1
+fi"#
+                )
+            } else {
+                command
+            };
+            buffer_command.push_str(format!("{command}\n\n").as_str());
+        }
+
+        if repeat {
+            buffer_command.push_str(
+                r#"me_elapsed=$(($(me_millis) - me_started))
+printf 'Iteration %d took %d.%03d seconds\n' "$me_iteration" $((me_elapsed / 1000)) $((me_elapsed % 1000)) >&2
+me_iteration=$((me_iteration + 1))
+done
+"#,
+            );
+        }
+
+        if self.script.tap {
+            buffer_command.push_str("echo \"1..$me_test\" >&5\nexit \"$me_failed\"\n");
+        }
+
+        buffer_command
+    }
+
+    /// Returns the commands as a PowerShell script, used on Windows where `/bin/sh` is not
+    /// available.  Only the separator, the annotations and the output prefix are supported, while
+    /// the script stops at the first failing command, like the shell script.
+    pub fn as_powershell_script(&self) -> String {
+        let mut buffer_command = String::new();
+        if self.script.preamble {
+            buffer_command.push_str(
+                r#"# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+$ErrorActionPreference = 'Stop'
+
+"#,
+            );
+        }
+
+        for (index, command) in self.commands.iter().enumerate() {
+            if self.script.annotate {
+                buffer_command.push_str(format!("# command {}\n", index + 1).as_str());
+            }
+
+            if self.script.prefix_output {
+                buffer_command.push_str(
+                    format!(
+                        "Write-Output ([string][char]30 + '{0}')\n[Console]::Error.WriteLine([string][char]30 + '{0}')\n",
+                        index + 1
+                    )
+                    .as_str(),
+                );
+            }
+
+            if let Some(separator) = &self.script.separator {
+                let escaped = separator.replace('\'', "''");
+                buffer_command.push_str(format!("Write-Output '{escaped}'\n").as_str());
+            }
+
+            if let Some(title) = command.title {
+                let escaped = title.replace('\'', "''");
+                buffer_command.push_str(format!("Write-Output '=== {escaped} ==='\n").as_str());
+            }
+
+            for (line_index, line) in command.lines.iter().enumerate() {
+                let prompt = if line_index == 0 { "$" } else { ">" };
+                let escaped = line.replace('\'', "''");
+                buffer_command.push_str(format!("Write-Output '{prompt} {escaped}'\n").as_str());
+            }
+
+            /* The script fails if the command does not exit with the expected status */
+            let check = match command.expected_status {
+                None => "if ($LASTEXITCODE) { exit $LASTEXITCODE }".to_string(),
+                Some(ExpectedStatus::Failure) => r#"if (-not $LASTEXITCODE) {
+  [Console]::Error.WriteLine('Expected the command to fail, but it succeeded')
+  exit 1
+}"#
+                .to_string(),
+                Some(ExpectedStatus::Status(status)) => format!(
+                    r#"if ($LASTEXITCODE -ne {status}) {{
+  [Console]::Error.WriteLine("Expected the command to exit with {status}, but it exited with $LASTEXITCODE")
+  exit 1
+}}"#
+                ),
+            };
+            buffer_command.push_str(format!("{command}\n{check}\n\n").as_str());
+        }
+
+        buffer_command
+    }
+}
+
+impl<'b, 'a> IntoIterator for &'b CommandBlocks<'a> {
+    type Item = &'b CommandBlock<'a>;
+    type IntoIter = std::slice::Iter<'b, CommandBlock<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.commands.iter()
+    }
+}
+
+impl Display for CommandBlocks<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for command in &self.commands {
+            writeln!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod parser {
+        use super::*;
+
+        #[test]
+        fn parse_empty_content() {
+            let content = "";
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_empty();
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_without_commands() {
+            let content = r#"# README
+
+No commands here!!
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_empty();
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_single_line_command() {
+            let content = r#"# README
+
+Before command
+
+```shell
+ls -la
+```
+
+After command
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["ls -la"], 6);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_multiple_single_line_command() {
+            let content = r#"# README
+
+```shell
+echo "Hello"
+```
+
+```shell
+ls -la
+```
+
+```shell
+echo "Goodbye"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec!["echo \"Hello\""],
+                    vec!["ls -la"],
+                    vec!["echo \"Goodbye\""],
+                ],
+                &[4, 8, 12],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_different_indentation() {
+            let content = r#"# README
+
+```shell
+echo "Hello"
+```
+
+- `ls` command
+
+  ```shell
+  ls -la
+  ```
+
+  1. `echo` command
+
+     ```shell
+     echo "Goodbye"
+     ```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec!["echo \"Hello\""],
+                    vec!["ls -la"],
+                    vec!["echo \"Goodbye\""],
+                ],
+                &[4, 10, 16],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_backslash() {
+            let content = r#"# README
+
+```shell
+java \
+  -jar target/app.jar
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["java \\", "  -jar target/app.jar"], 4);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_blank_lines_within_multi_line_commands() {
+            let content = r#"# README
+
+```shell
+cat << EOF
+First
+
+Last
+EOF
+echo "Hello" \
+
+echo "World"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "cat << EOF",
+                    "First",
+                    "",
+                    "Last",
+                    "EOF",
+                    "echo \"Hello\" \\",
+                    "",
+                    "echo \"World\"",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_fence_mentioned_in_prose() {
+            let content = r#"# README
+
+Start the code block with ```shell and close it with ```.
+
+```shell
+echo "Hello ```shell"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo \"Hello ```shell\""], 6);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_fence_in_list_item() {
+            let content = r#"# README
+
+1. ```shell
+   echo "First"
+   ```
+- ```shell
+  echo "Second"
+  ```
+
+```shell
+echo "Third"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec!["echo \"First\""],
+                    vec!["echo \"Second\""],
+                    vec!["echo \"Third\""],
+                ],
+                &[4, 7, 11],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_trailing_whitespace_after_closing_fence() {
+            let content = "```shell\necho \"Hello\"\n```   \n\n```shell\necho \"World\"\n```\t\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![vec!["echo \"Hello\""], vec!["echo \"World\""]],
+                &[2, 6],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_text_after_closing_fence() {
+            let content = "```shell\necho \"Hello\"\n``` text\necho \"World\"\n```\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo \"Hello\"", "``` text", "echo \"World\""], 2);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_unclosed_code_block() {
+            let content = "# README\n\n```shell\necho \"Hello\"\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options).expect_err("The code block is not closed");
+            assert_eq!(
+                "Failed to find closing code block for the command on line 4",
+                parsed.message()
+            );
+            assert_eq!(Some(4), parsed.line_number());
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_quoted_backslash() {
+            let content = r#"# README
+
+```shell
+echo 'a\'
+echo b\\
+echo c
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec![r"echo 'a\'", r"echo b\\", "echo c"], 4);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_here_document_without_indentation() {
+            let content = r#"# README
+
+```shell
+patch -p1 -u './Test.java' << EOF
+--- ./Test.java
++++ ./Test.java
+@@ -1,3 +1,2 @@
+ package demo;
+
+ -import java.io.Console;
+EOF
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "patch -p1 -u './Test.java' << EOF",
+                    "--- ./Test.java",
+                    "+++ ./Test.java",
+                    "@@ -1,3 +1,2 @@",
+                    " package demo;",
+                    "",
+                    " -import java.io.Console;",
+                    "EOF",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_here_document_with_indentation() {
+            let content = r#"# README
+
+- Step 1
+
+  ```shell
+  patch -p1 -u './Test.java' << EOF
+  --- ./Test.java
+  +++ ./Test.java
+  @@ -1,3 +1,2 @@
+   package demo;
+
+   -import java.io.Console;
+  EOF
+  ```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "patch -p1 -u './Test.java' << EOF",
+                    "--- ./Test.java",
+                    "+++ ./Test.java",
+                    "@@ -1,3 +1,2 @@",
+                    " package demo;",
+                    "",
+                    " -import java.io.Console;",
+                    "EOF",
+                ],
+                6,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_here_document_with_trailing_whitespace() {
+            let content = "```shell\ncat << 'EOF' > hello.txt\nHello\nEOF  \necho 'Done'\n```\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec!["cat << 'EOF' > hello.txt", "Hello", "EOF", "echo 'Done'"],
+                2,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_prompt_within_here_document() {
+            let content = r#"# README
+
+```shell
+$ cat << EOF > session.txt
+$ echo inside
+EOF
+$ echo outside
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "cat << EOF > session.txt",
+                    "$ echo inside",
+                    "EOF",
+                    "echo outside",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_multiple_here_documents() {
+            let content = "```shell\ncat << A - << 'B'\nB \nA \nB \necho 'Done'\n```\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["cat << A - << 'B'", "B ", "A", "B", "echo 'Done'"], 2);
+            assert_eq!(expected, parsed);
+            assert_eq!(
+                vec!["A", "B"],
+                CommandBlock::find_here_document_delimiters("cat << A - << 'B'")
+            );
+            assert_eq!(
+                Vec::<&str>::new(),
+                CommandBlock::find_here_document_delimiters("cat <<< 'A'")
+            );
+        }
+
+        #[test]
+        fn find_here_document_delimiters_outside_quotes_and_arithmetic() {
+            for (line, expected) in [
+                ("cat <<-EOF", vec!["EOF"]),
+                ("cat << \\EOF", vec!["EOF"]),
+                ("echo $((1 << 2))", vec![]),
+                ("(( x <<= 1 ))", vec![]),
+                ("echo $(( (1 + 1) << 2 )) && cat << A", vec!["A"]),
+                ("echo '<< A' \"<< B\"", vec![]),
+                ("echo 'a\\' << A", vec!["A"]),
+                ("echo hi # << A", vec![]),
+                ("echo << 2", vec![]),
+                ("echo <<", vec![]),
+            ] {
+                assert_eq!(
+                    expected,
+                    CommandBlock::find_here_document_delimiters(line),
+                    "{}",
+                    line
+                );
+            }
+        }
+
+        #[test]
+        fn parse_content_with_prompt_after_arithmetic_shift() {
+            let content = "```shell\n$ echo $((1 << 2))\n$ echo hi\n```\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo $((1 << 2))", "echo hi"], 2);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_one_multi_line_command_block() {
+            let content = r#"# README
+
+```shell
+while [ "$(curl --silent --output /dev/null --write-out '%{http_code}' 'http://localhost:8080')" -ne '200' ]
+do
+  echo 'Waiting for the application to start'
+  sleep 1
+done
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec![
+                "while [ \"$(curl --silent --output /dev/null --write-out '%{http_code}' 'http://localhost:8080')\" -ne '200' ]",
+                "do",
+                "  echo 'Waiting for the application to start'",
+                "  sleep 1",
+                "done",
+            ], 4);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_multiple_single_line_commands() {
+            let content = r#"# README
+
+```shell
+echo "Line 1"
+echo "Line 2"
+echo "Line 3"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec!["echo \"Line 1\"", "echo \"Line 2\"", "echo \"Line 3\""],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_multiple_multi_line_commands() {
+            let content = r#"# README
+
+```shell
+echo "Before"
+java \
+  -jar target/app-1.jar
+java \
+  -jar target/app-2.jar
+echo "After"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "echo \"Before\"",
+                    "java \\",
+                    "  -jar target/app-1.jar",
+                    "java \\",
+                    "  -jar target/app-2.jar",
+                    "echo \"After\"",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_multiple_languages() {
+            let content = r#"# README
+
+```shell
+echo "Shell 1"
+```
+
+```bash
+echo "Bash 1"
+```
+
+```python
+print("Python")
+```
+
+```shell
+echo "Shell 2"
+```
+
+```bash
+echo "Bash 2"
+```
+"#;
+
+            let languages = vec!["shell".to_string(), "bash".to_string()];
+            let options = Options::new(content).with_languages(&languages);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec!["echo \"Shell 1\""],
+                    vec!["echo \"Bash 1\""],
+                    vec!["echo \"Shell 2\""],
+                    vec!["echo \"Bash 2\""],
+                ],
+                &[4, 8, 16, 20],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_program_and_multiline() {
+            let content = r#"# README
+
+```shell
+$   rm -rf target
+```
+
+```shell
+java \
+  --version
+```
+
+```shell
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = options.build();
+            let commands: Vec<_> = parsed
+                .iter()
+                .map(|command| (command.program(), command.is_multiline()))
+                .collect();
+            assert_eq!(
+                vec![(Some("rm"), false), (Some("java"), true), (None, false)],
+                commands
+            );
+        }
+
+        #[test]
+        fn parse_content_with_default_prompt() {
+            let content = r#"# README
+
+```shell
+$ echo "Hello"
+$ java \
+  -jar target/app.jar
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec!["echo \"Hello\"", "java \\", "  -jar target/app.jar"],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_default_prompt_without_space() {
+            let content = r#"# README
+
+```shell
+$echo "Hello"
+$./gradlew build
+$/usr/bin/env true
+$VAR=1
+$var=1
+${HOME}/bin/tool
+$(pwd)/tool
+```
+"#;
+
+            let options = Options::new(content).with_prompt_without_space(true);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "echo \"Hello\"",
+                    "./gradlew build",
+                    "/usr/bin/env true",
+                    "$VAR=1",
+                    "$var=1",
+                    "${HOME}/bin/tool",
+                    "$(pwd)/tool",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+
+            let content = "```shell\n$echo \"Hello\"\n$./gradlew build\n  $editor \"$f\"\n```\n";
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec!["$echo \"Hello\"", "./gradlew build", "  $editor \"$f\""],
+                2,
+            );
+            assert_eq!(expected, parsed);
+
+            let options = Options::new(content).with_prompt_without_space(true);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec!["echo \"Hello\"", "./gradlew build", "  $editor \"$f\""],
+                2,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_byte_order_mark() {
+            let content = "\u{feff}```shell\necho \"Hello\"\n```\n";
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo \"Hello\""], 2);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_trailing_pipe() {
+            let content = r#"# README
+
+```shell
+$ cat README.md |
+  grep 'shell' |
+  wc -l
+$ test -f README.md ||
+  touch README.md
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "cat README.md |",
+                    "  grep 'shell' |",
+                    "  wc -l",
+                    "test -f README.md ||",
+                    "  touch README.md",
+                ],
+                4,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_trailing_and() {
+            let content = r#"# README
+
+```shell
+$ echo a &&
+echo b
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo a &&", "echo b"], 4);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_other_languages() {
+            let content = r#"# README
+
+```python
+# Run the example with shell
+print("```shell")
+```
+
+```json
+{ "shell": "echo \"JSON\"" }
+```
+
+```
+$ echo "Plain"
+```
+
+Mention the ```` ```shell ```` fence inline.
+
+```shell
+echo "Shell"
+```
+
+~~~python
+```shell
+echo "Python"
+```
+~~~
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo \"Shell\""], 19);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_nested_fence() {
+            let content = r#"# README
+
+````shell
+cat << EOF > README.md
+```shell
+echo "Inner"
+```
+EOF
+````
+
+~~~shell
+echo "Tildes"
+~~~
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec![
+                        "cat << EOF > README.md",
+                        "```shell",
+                        "echo \"Inner\"",
+                        "```",
+                        "EOF",
+                    ],
+                    vec!["echo \"Tildes\""],
+                ],
+                &[4, 12],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_within_blockquote() {
+            let content = r#"# README
+
+> Run the following
+>
+> ```shell
+> $ echo "Quoted"
+>$ echo "Without space"
+>
+> ```
+>
+> > ```shell
+> > echo "Nested"
+> > ```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![
+                    vec!["echo \"Quoted\"", "echo \"Without space\"", ""],
+                    vec!["echo \"Nested\""],
+                ],
+                &[6, 12],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_indented_prompt() {
+            let content = r#"# README
+
+1. Run the commands
+
+   ```shell
+   $ echo "Hello"
+     $ echo "Indented"
+     echo "Not a prompt"
+   ```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "echo \"Hello\"",
+                    "echo \"Indented\"",
+                    "  echo \"Not a prompt\"",
+                ],
+                6,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_custom_prompts() {
+            let content = r#"# README
+
+```shell
+% echo "Zsh"
+# @tag root
+# whoami
+$ echo "Not a prompt"
+```
+"#;
+
+            let prompts = vec!["% ".to_string(), "# ".to_string()];
+            let options = Options::new(content).with_prompts(&prompts);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(
+                of_strs(vec!["echo \"Zsh\"", "whoami", "$ echo \"Not a prompt\""]),
+                &[4],
+            );
+            expected.commands[0].tag = Some("root");
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_with_tag() {
+            let content = r#"# README
+
+```shell
+# @tag setup
+echo "Setup"
+```
+
+```shell
+$ # @tag build
+mvn package
+```
+
+```shell
+echo "Untagged"
+```
+"#;
+
+            let options = Options::new(content).with_tag(Some("build"));
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(of_strs(vec!["mvn package"]), &[10]);
+            expected.commands[0].tag = Some("build");
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_with_tag_within_here_document() {
+            let content = r#"# README
+
+```shell
+cat << EOF > notes.md
+# @tag kept
+EOF
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["cat << EOF > notes.md", "# @tag kept", "EOF"], 4);
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_section() {
+            let content = r#"# README
+
+```shell
+echo "Before"
+```
+
+## Build
+
+```shell
+mvn package
+```
+
+### Native ###
+
+```shell
+mvn -Pnative package
+```
+
+## Test
+
+```shell
+mvn test
+```
+"#;
+
+            let options = Options::new(content).with_section(Some("Build"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![vec!["mvn package"], vec!["mvn -Pnative package"]],
+                &[10, 16],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_section_ignores_comments_within_code_blocks() {
+            let content = r#"# README
+
+## Build
+
+```shell
+# Build
+mvn package
+```
+
+```shell
+mvn verify
+```
+
+## Test
+
+```shell
+mvn test
+```
+"#;
+
+            let options = Options::new(content).with_section(Some("Build"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![vec!["# Build", "mvn package"], vec!["mvn verify"]],
+                &[6, 11],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_unknown_section() {
+            let content = r#"# README
+
+## Build
+
+```shell
+mvn package
+```
+"#;
+
+            let options = Options::new(content).with_section(Some("Deploy"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ParserError::err("Unknown section: Deploy".to_string());
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_sudo_match() {
+            let content = r#"# README
+
+```shell
+apt-get install curl
+```
+
+```shell
+sudo apt-get install git
+```
+
+```shell
+ls
+```
+"#;
+
+            let regex = Regex::new("^(sudo )?apt-get").expect("Invalid regex");
+            let options = Options::new(content).with_sudo_match(Some(&regex));
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            let sudo: Vec<bool> = parsed.iter().map(CommandBlock::is_sudo).collect();
+            assert_eq!(vec![true, false, false], sudo);
+        }
+
+        #[test]
+        fn parse_content_with_sudo_match_on_later_line() {
+            let content = r#"# README
+
+```shell
+rm -rf build
+# apt-get is only needed once
+  apt-get install \
+    curl
+cat << EOF > notes.txt
+apt-get is not run here
+EOF
+```
+"#;
+
+            let regex = Regex::new("^apt-get").expect("Invalid regex");
+            let options = Options::new(content).with_sudo_match(Some(&regex));
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            let command = parsed.iter().next().expect("Missing command");
+            assert_eq!(vec![2], command.sudo);
+            assert_eq!(
+                vec![
+                    "rm -rf build",
+                    "# apt-get is only needed once",
+                    "  sudo apt-get install \\",
+                    "    curl",
+                    "cat << EOF > notes.txt",
+                    "apt-get is not run here",
+                    "EOF",
+                ],
+                command.script_lines()
+            );
+        }
+
+        #[test]
+        fn parse_content_with_unknown_tag() {
+            let content = r#"# README
+
+```shell
+# @tag setup
+echo "Setup"
+```
+"#;
+
+            let options = Options::new(content).with_tag(Some("build"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ParserError::err("Unknown tag: build".to_string());
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_execute_from_and_until() {
+            let content = r#"# README
+
+```shell
+$ echo "Before"
+```
+
+```shell
+$ echo "First"
+```
+
+```shell
+$ echo "Last"
+$ echo "Same block"
+```
+
+```shell
+$ echo "After"
+```
+"#;
+
+            let expected = between_lines(
+                ok_of_multi_strs(
+                    vec![
+                        vec!["echo \"First\""],
+                        vec!["echo \"Last\"", "echo \"Same block\""],
+                    ],
+                    &[8, 12],
+                ),
+                Some((8, "$ echo \"First\"")),
+                Some((12, "$ echo \"Last\"")),
+            );
+
+            let options = Options::new(content)
+                .with_execute_from(Some("$ echo \"First\""))
+                .with_execute_until(Some("$ echo \"Last\""));
+            assert_eq!(expected, CommandBlocks::parse(&options));
+
+            let options = Options::new(content)
+                .with_execute_from(Some("echo \"First\""))
+                .with_execute_until(Some("echo \"Last\""));
+            assert_eq!(expected, CommandBlocks::parse(&options));
+
+            let options = Options::new(content)
+                .with_execute_from_prefix(Some("$ echo \"Fi"))
+                .with_execute_until_prefix(Some("echo \"La"));
+            assert_eq!(expected, CommandBlocks::parse(&options));
+        }
+
+        #[test]
+        fn parse_content_execute_from_case_sensitive() {
+            let content = r#"# README
+
+```shell
+export env=dev
+```
+
+```shell
+export ENV=prod
+```
+"#;
+
+            let options = Options::new(content).with_execute_from(Some("export ENV=prod"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = between_lines(
+                ok_of_strs(vec!["export ENV=prod"], 8),
+                Some((8, "export ENV=prod")),
+                None,
+            );
+            assert_eq!(expected, parsed);
+
+            let options = Options::new(content).with_execute_from(Some("EXPORT ENV=PROD"));
+            let parsed = CommandBlocks::parse(&options);
+            let expected =
+                ParserError::err("Unknown command to execute from: EXPORT ENV=PROD".to_string());
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_execute_from_and_until_duplicates() {
+            let content = r#"# README
+
+```shell
+$ echo "Stop"
+```
+
+```shell
+$ echo "Start"
+```
+
+```shell
+  echo "Start"
+$ echo "Stop"
+```
+
+```shell
+echo "Stop"
+```
+"#;
+
+            let options = Options::new(content)
+                .with_execute_from(Some("echo \"Start\""))
+                .with_execute_until(Some("echo \"Stop\""));
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            assert_eq!(Some((8, "$ echo \"Start\"")), parsed.execute_from_line());
+            assert_eq!(Some((13, "$ echo \"Stop\"")), parsed.execute_until_line());
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            assert_eq!(None, parsed.execute_from_line());
+            assert_eq!(None, parsed.execute_until_line());
+        }
+
+        #[test]
+        fn parse_content_unknown_execute_from() {
+            let content = r#"# README
+
+```shell
+$ echo "Hello"
+```
+"#;
+
+            let options = Options::new(content).with_execute_from(Some("echo \"Bye\""));
+            let parsed = CommandBlocks::parse(&options);
+            let expected =
+                ParserError::err("Unknown command to execute from: echo \"Bye\"".to_string());
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_skip_first() {
+            let content = r#"# README
+
+```shell
+echo "First"
+```
+
+```shell
+echo "Second"
+```
+
+```shell
+echo "Third"
+```
+"#;
+
+            let options = Options::new(content)
+                .with_skip_first(1)
+                .with_execute_until(Some("echo \"Second\""));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = between_lines(
+                ok_of_strs(vec!["echo \"Second\""], 8),
+                None,
+                Some((8, "echo \"Second\"")),
+            );
+            assert_eq!(expected, parsed);
+
+            let options = Options::new(content).with_skip_first(4);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ParserError::err(
+                "Cannot skip the first 4 commands as only 3 were found".to_string(),
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_first_only() {
+            let content = r#"# README
+
+```shell
+echo "First"
+```
+
+```shell
+echo "Second"
+```
+
+```shell
+echo "Third"
+```
+"#;
+
+            let options = Options::new(content).with_first_only(true);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(vec!["echo \"First\""], 4);
+            assert_eq!(expected, parsed);
+
+            let options = Options::new(content)
+                .with_first_only(true)
+                .with_execute_from(Some("echo \"Second\""));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = between_lines(
+                ok_of_strs(vec!["echo \"Second\""], 8),
+                Some((8, "echo \"Second\"")),
+                None,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_deny() {
+            let content = r#"# README
+
+```shell
+echo "Clean"
+```
+
+```shell
+rm -rf /
+```
+"#;
+
+            let deny = vec![Regex::new(r"rm\s+-rf\s+/$").expect("Invalid deny regex")];
+            let options = Options::new(content).with_deny(&deny);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = Err(ParserError::new(
+                r"Refusing to run the command on line 8, which matches the deny pattern 'rm\s+-rf\s+/$': rm -rf /"
+                    .to_string(),
+            )
+            .at_line(Some(8)));
+            assert_eq!(expected, parsed);
+
+            let options = Options::new(content)
+                .with_deny(&deny)
+                .with_execute_until(Some("echo \"Clean\""));
+            let parsed = CommandBlocks::parse(&options);
+            let expected = between_lines(
+                ok_of_strs(vec!["echo \"Clean\""], 4),
+                None,
+                Some((4, "echo \"Clean\"")),
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_prepend_and_append() {
+            let content = r#"# README
+
+```shell
+echo "Hello"
+```
+"#;
+
+            let prepend = vec!["set -u".to_string(), "cd /tmp".to_string()];
+            let append = vec!["./cleanup.sh".to_string()];
+            let options = Options::new(content)
+                .with_prepend(&prepend)
+                .with_append(&append);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = of_multi_strs(vec![
+                vec!["set -u"],
+                vec!["cd /tmp"],
+                vec!["echo \"Hello\""],
+                vec!["./cleanup.sh"],
+            ]);
+            expected.commands[2].line_number = Some(4);
+            expected.found = 1;
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_with_block_attributes() {
+            let content = r#"# README
+
+```shell {skip}
+echo "Skipped"
+```
+
+```shell {doc}
+echo "Documentation"
+```
+
+```shell {timeout=30}
+sleep 10
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(of_strs(vec!["sleep 10"]), &[12]);
+            expected.commands[0].timeout = Some(30);
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_with_block_title() {
+            let content = r#"# README
+
+```shell title="Install step"
+make install
+```
+
+```shell {timeout=30, title="Wait, then run"}
+sleep 10
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(
+                of_multi_strs(vec![vec!["make install"], vec!["sleep 10"]]),
+                &[4, 8],
+            );
+            expected.commands[0].title = Some("Install step");
+            expected.commands[1].timeout = Some(30);
+            expected.commands[1].title = Some("Wait, then run");
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_with_expected_status() {
+            let content = r#"# README
+
+```shell expect_fail
+cat missing.txt
+```
+
+```shell {expect=2}
+ls missing
+```
+
+```shell
+# expect: 127
+$ unknown-command
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(
+                of_multi_strs(vec![
+                    vec!["cat missing.txt"],
+                    vec!["ls missing"],
+                    vec!["unknown-command"],
+                ]),
+                &[4, 8, 13],
+            );
+            expected.commands[0].expected_status = Some(ExpectedStatus::Failure);
+            expected.commands[1].expected_status = Some(ExpectedStatus::Status(2));
+            expected.commands[2].expected_status = Some(ExpectedStatus::Status(127));
+            assert_eq!(Ok(expected), parsed);
+
+            let options = Options::new("```shell {expect=fail}\nfalse\n```\n");
+            let parsed = CommandBlocks::parse(&options);
+            let expected = Err(ParserError::new(
+                "Invalid expect attribute: expect=fail on line 1".to_string(),
+            )
+            .at_line(Some(1)));
+            assert_eq!(expected, parsed);
+
+            let content = r#"```shell
+# expect: a warning
+cat << EOF > notes.txt
+# expect: 1
+EOF
+```
+"#;
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_strs(
+                vec![
+                    "# expect: a warning",
+                    "cat << EOF > notes.txt",
+                    "# expect: 1",
+                    "EOF",
+                ],
+                2,
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_parser_dump() {
+            thread_local! {
+                static DUMPED: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(vec![]) };
+            }
+
+            let content = r#"# README
+
+  ```shell
+  cat << EOF
+  Hello
+  EOF
+  ```
+
+```java
+System.exit(1);
+```
+"#;
+
+            let options = Options::new(content).with_parser_dump(Some(|line| {
+                DUMPED.with(|dumped| dumped.borrow_mut().push(line.to_string()))
+            }));
+            CommandBlocks::parse(&options).expect("Failed to parse the content");
+
+            let expected = vec![
+                "    1 | text          | offset - | here-documents - | - | # README",
+                "    2 | text          | offset - | here-documents - | - | ",
+                "    3 | command block | offset 2 | here-documents - | - |   ```shell",
+                "    4 | command block | offset 2 | here-documents EOF | - |   cat << EOF",
+                "    5 | command block | offset 2 | here-documents EOF | - |   Hello",
+                "    6 | command block | offset 2 | here-documents - | - |   EOF",
+                "    7 | text          | offset - | here-documents - | command |   ```",
+                "    8 | text          | offset - | here-documents - | - | ",
+                "    9 | other block   | offset 0 | here-documents - | - | ```java",
+                "   10 | other block   | offset 0 | here-documents - | - | System.exit(1);",
+                "   11 | text          | offset - | here-documents - | - | ```",
+            ];
+            assert_eq!(expected, DUMPED.with(|dumped| dumped.take()));
+        }
+
+        #[test]
+        fn parse_content_with_shuffle() {
+            let content = r#"# README
+
+```shell
+echo "A"
+```
+
+```shell group=pair
+echo "B"
+```
+
+```shell group=pair
+echo "C"
+```
+
+```shell
+echo "D"
+echo "E"
+```
+
+```shell
+echo "F"
+```
+"#;
+
+            let order = |seed| {
+                let options = Options::new(content).with_shuffle(seed);
+                CommandBlocks::parse(&options)
+                    .expect("Failed to parse the content")
+                    .iter()
+                    .map(CommandBlock::text)
+                    .collect::<Vec<_>>()
+            };
+
+            let original = order(None);
+            assert_eq!(original, order(None));
+            assert_eq!(order(Some(7)), order(Some(7)));
+            assert!((0..16).any(|seed| order(Some(seed)) != original));
+
+            for seed in 0..16 {
+                let shuffled = order(Some(seed));
+                let mut sorted = shuffled.clone();
+                sorted.sort();
+                assert_eq!(original, sorted);
+
+                let b = shuffled.iter().position(|text| text == "echo \"B\"");
+                let c = shuffled.iter().position(|text| text == "echo \"C\"");
+                assert_eq!(b.map(|b| b + 1), c);
+            }
+        }
+
+        #[test]
+        fn parse_content_with_block_group() {
+            let content = r#"# README
+
+```shell group=server
+export PORT=8080
+```
+
+```shell
+echo "Unrelated"
+```
+
+```shell {group="server"}
+echo "$PORT"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(
+                of_multi_strs(vec![
+                    vec!["export PORT=8080"],
+                    vec!["echo \"$PORT\""],
+                    vec!["echo \"Unrelated\""],
+                ]),
+                &[4, 12, 8],
+            );
+            expected.commands[0].group = Some("server");
+            expected.commands[1].group = Some("server");
+            assert_eq!(Ok(expected), parsed);
+
+            let options = Options::new(content).with_group(Some("server"));
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            let lines: Vec<_> = parsed.iter().flat_map(CommandBlock::lines).collect();
+            assert_eq!(vec![&"export PORT=8080", &"echo \"$PORT\""], lines);
+
+            let options = Options::new(content).with_group(Some("client"));
+            let parsed = CommandBlocks::parse(&options);
+            assert_eq!(
+                ParserError::err("Unknown group: client".to_string()),
+                parsed
+            );
+        }
+
+        #[test]
+        fn parse_content_with_ignored_block() {
+            let content = r#"# README
+
+```shell
+echo "Executed"
+```
+
+```shell ignore
+rm -rf /
+```
+
+```shell ignored
+echo "Not a marker"
+```
+"#;
+
+            let options = Options::new(content);
+            let parsed = CommandBlocks::parse(&options);
+            let expected = ok_of_multi_strs(
+                vec![vec!["echo \"Executed\""], vec!["echo \"Not a marker\""]],
+                &[4, 12],
+            );
+            assert_eq!(expected, parsed);
+        }
+
+        #[test]
+        fn parse_content_with_malformed_block_attributes() {
+            let malformed = [
+                (
+                    "```shell {skip\n```\n",
+                    "Missing closing brace in the code block attributes on line 1",
+                ),
+                (
+                    "```shell {timeout=soon}\n```\n",
+                    "Invalid timeout attribute: timeout=soon on line 1",
+                ),
+                (
+                    "```shell {run}\n```\n",
+                    "Unknown code block attribute: run on line 1",
+                ),
+                (
+                    "```shell title=\"Install\n```\n",
+                    "Missing closing quote in the code block attributes on line 1",
+                ),
+            ];
+
+            for (content, message) in malformed {
+                let options = Options::new(content);
+                let parsed = CommandBlocks::parse(&options);
+                let expected = ParserError::new(message.to_string()).at_line(Some(1));
+                assert_eq!(Err(expected), parsed);
+            }
+        }
+
+        #[test]
+        fn parse_content_skip_commands() {
+            let content = r#"# README
+
+```shell
+echo "Line 1"
+```
+
+```shell
+echo "Hello there"
+```
+
+```shell
+echo "Line 2"
+echo "Hello there"
+```
+"#;
+
+            let skip_commands = Regex::new(r"Line \d").expect("Invalid skip commands regex");
+            let options = Options::new(content).with_skip_commands(Some(&skip_commands));
+            let parsed = CommandBlocks::parse(&options);
+            let mut expected = at_line_numbers(of_strs(vec!["echo \"Hello there\""]), &[8]);
+            expected.skipped = 2;
+            assert_eq!(Ok(expected), parsed);
+        }
+
+        #[test]
+        fn parse_content_len_and_iterate() {
+            let content = r#"# README
+
+```shell
+echo "First"
+```
+
+```shell
+echo "Second"
+echo "Same block"
+```
+"#;
+
+            let append = vec!["echo \"Appended\"".to_string()];
+            let options = Options::new(content).with_append(&append);
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            assert_eq!(3, parsed.len());
+            assert_eq!(2, parsed.found());
+            assert!(!parsed.is_empty());
+
+            let mut texts = vec![];
+            for command in &parsed {
+                texts.push(command.text());
+            }
+            assert_eq!(
+                vec![
+                    "echo \"First\"",
+                    "echo \"Second\"\necho \"Same block\"",
+                    "echo \"Appended\"",
+                ],
+                texts
+            );
+            assert_eq!(
+                texts,
+                parsed.iter().map(CommandBlock::text).collect::<Vec<_>>()
+            );
+
+            let options = Options::new("# README\n");
+            let parsed = CommandBlocks::parse(&options).expect("Failed to parse the content");
+            assert_eq!(0, parsed.len());
+            assert!(parsed.is_empty());
+            assert_eq!(None, (&parsed).into_iter().next());
+        }
+    }
+
+    mod formatter {
+        use super::*;
+
+        #[test]
+        fn format_empty_command() {
+            let commands = empty();
+            let formatted = format!("{}", commands);
+            let expected = "";
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_one_single_line_command() {
+            let commands = of_strs(vec!["ls -la"]);
+            let formatted = format!("{}", commands);
+            let expected = r#"ls -la
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_multiple_single_line_command() {
+            let commands = of_strs(vec!["echo \"Hello\"", "ls -la", "echo \"Goodbye\""]);
+            let formatted = format!("{}", commands);
+            let expected = r#"echo "Hello"
+ls -la
+echo "Goodbye"
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_one_multi_line_command() {
+            let commands = of_strs(vec!["java \\", " -jar target/app.jar"]);
+            let formatted = format!("{}", commands);
+            let expected = r#"java \
+ -jar target/app.jar
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_multi_line_command_with_blank_lines() {
+            let commands = of_strs(vec!["cat << EOF", "", "EOF", "echo \\", "", "ls"]);
+            let formatted = format!("{}", commands);
+            let expected = "cat << EOF\n\nEOF\necho \\\n\nls\n";
+            assert_eq!(expected, formatted);
+
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '%s\n' '---'
+printf '%s\n' '$ cat << EOF'
+printf '%s\n' '> '
+printf '%s\n' '> EOF'
+printf '%s\n' '> echo \'
+printf '%s\n' '> '
+printf '%s\n' '> ls'
+cat << EOF
+
+EOF
+echo \
+
+ls
+
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_multiple_single_line_commands() {
+            let commands = of_strs(vec![
+                "echo \"Line 1\"",
+                "echo \"Line 2\"",
+                "echo \"Line 3\"",
+            ]);
+            let formatted = format!("{}", commands);
+            let expected = r#"echo "Line 1"
+echo "Line 2"
+echo "Line 3"
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_multiple_multi_line_commands() {
+            let commands = of_multi_strs(vec![
+                vec!["echo \"Before\""],
+                vec!["java \\", " -jar target/app-1.jar"],
+                vec!["java \\", " -jar target/app-2.jar"],
+                vec!["echo \"After\""],
+            ]);
+
+            let formatted = format!("{}", commands);
+            let expected = r#"echo "Before"
+java \
+ -jar target/app-1.jar
+java \
+ -jar target/app-2.jar
+echo "After"
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_as_shell_script() {
+            let commands = of_strs(vec!["java \\", " -XshowSettings:vm \\", " --version"]);
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '%s\n' '---'
+printf '%s\n' '$ java \'
+printf '%s\n' '>  -XshowSettings:vm \'
+printf '%s\n' '>  --version'
+java \
+ -XshowSettings:vm \
+ --version
+
+"#;
+            assert_eq!(expected, formatted);
+        }
+
+        #[test]
+        fn format_as_shell_script_with_retry() {
+            let mut commands = of_strs(vec!["curl --fail 'http://localhost:8080'"]);
+            commands.script.execution_mode = ExecutionMode::RetryWithBackoff {
+                attempts: 3,
+                delay_ms: 500,
+            };
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+me_retry() {
+  me_attempts="$1"
+  me_delay_ms="$2"
+  me_attempt=1
+  while :; do
+    set +e
+    (
+      set -e
+      "$3"
+    )
+    me_status=$?
+    set -e
+    if [ "$me_status" -eq 0 ]; then
+      return 0
+    fi
+    if [ "$me_attempt" -ge "$me_attempts" ]; then
+      echo "Command failed after $me_attempt attempts" >&2
+      return 1
+    fi
+    echo "Attempt $me_attempt failed, retrying in $me_delay_ms milliseconds" >&2
+    sleep "$((me_delay_ms / 1000)).$(printf '%03d' "$((me_delay_ms % 1000))")"
+    me_attempt=$((me_attempt + 1))
+    me_delay_ms=$((me_delay_ms * 2))
+  done
 }
 
-impl<'a> CommandBlocks<'a> {
-    fn parse(options: &'a Options<'a>) -> Result<Self, ParserError> {
-        let mut commands = vec![];
-        let mut buffered_commands = vec![];
+printf '%s\n' '---'
+printf '%s\n' '$ curl --fail '\''http://localhost:8080'\'''
+me_command() {
+curl --fail 'http://localhost:8080'
+}
+me_retry 3 500 me_command
 
-        let mut within_command_block = None;
-        let mut skip_command_block = false;
+"#;
+            assert_eq!(expected, formatted);
+        }
 
-        for line in options.content.lines() {
-            if let Some(offset) = line.find("```shell") {
-                if within_command_block.is_some() {
-                    return ParserError::err("Nested code block are not supported".to_string());
-                }
+        #[test]
+        fn format_as_list() {
+            let commands = of_multi_strs(vec![
+                vec!["echo \"Before\""],
+                vec!["cat << EOF", "Line 1", "", "Line 2", "EOF"],
+                vec!["echo \"After\""],
+            ]);
+            let formatted = commands.as_list();
+            let expected =
+                "echo \"Before\"\n\ncat << EOF\n\tLine 1\n\t\n\tLine 2\n\tEOF\n\necho \"After\"\n";
+            assert_eq!(expected, formatted);
+        }
 
-                within_command_block = Some(offset);
-                continue;
-            }
+        #[test]
+        fn iterate_commands() {
+            let commands = of_multi_strs(vec![vec!["echo \"Before\""], vec!["ls \\", "  -la"]]);
+            let texts: Vec<String> = commands.iter().map(|command| command.text()).collect();
+            assert_eq!(vec!["echo \"Before\"", "ls \\\n  -la"], texts);
+        }
 
-            if let Some(offset) = within_command_block {
-                if line.len() > offset && line[offset..].eq("```") {
-                    if !skip_command_block {
-                        commands.push(CommandBlock {
-                            lines: buffered_commands,
-                        });
-                        buffered_commands = vec![];
-                    }
+        #[test]
+        fn format_as_json() {
+            let commands = of_multi_strs(vec![
+                vec!["echo \"Before\""],
+                vec!["cat << EOF", "Line 1", "EOF"],
+            ]);
+            let formatted = commands.as_json();
+            let expected = r#"[{"command":"echo \"Before\"","lines":["echo \"Before\""],"tag":null,"line_number":null},{"command":"cat << EOF\nLine 1\nEOF","lines":["cat << EOF","Line 1","EOF"],"tag":null,"line_number":null}]"#;
+            assert_eq!(expected, formatted);
+        }
 
-                    within_command_block = None;
-                    skip_command_block = false;
-                    continue;
-                }
-            }
+        #[test]
+        fn format_as_list_round_trip() {
+            let content = r#"# README
 
-            if skip_command_block {
-                continue;
-            }
+```shell
+echo "Before"
+```
 
-            if let Some(offset) = within_command_block {
-                let command_line = if line.len() > offset {
-                    &line[offset..]
-                } else {
-                    ""
-                };
+```shell
+java \
+  -jar target/app.jar
+```
 
-                buffered_commands.push(command_line);
+```shell
+patch -p1 -u './Test.java' << EOF
+--- ./Test.java
++++ ./Test.java
 
-                /* Check if the command needs to be skipped and clear the buffer if so */
-                if let Some(regex) = &options.skip_commands {
-                    if regex.is_match(&buffered_commands.join(" ")) {
-                        skip_command_block = true;
-                        buffered_commands.clear();
-                        continue;
-                    }
-                }
-            }
-        }
+ -import java.io.Console;
+EOF
+```
+"#;
 
-        if !buffered_commands.is_empty() {
-            ParserError::err("Failed to find closing code block".to_string())
-        } else {
-            Ok(CommandBlocks { commands })
+            let options = Options::new(content);
+            let commands = options.build();
+            let list = commands.as_list();
+            let list_options = Options::new(&list);
+            let parsed =
+                CommandBlocks::parse_list(&list_options).expect("Failed to parse the list");
+            let texts = |commands: &CommandBlocks<'_>| {
+                commands
+                    .iter()
+                    .map(|command| command.text())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(texts(&commands), texts(&parsed));
         }
-    }
 
-    pub(crate) fn as_shell_script(&self) -> String {
-        let mut buffer_command = String::new();
-        buffer_command.push_str(
-            r#"#!/bin/sh
+        #[test]
+        fn format_as_shell_script_with_annotate() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Hello'"], vec!["ls -la"]]);
+            commands.script.annotate = true;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
 # Generated by the MARKDOWN executor
 # This file is automatically deleted once the execution completes
 
 set -e
 
-"#,
-        );
+# command 1
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Hello'\'''
+echo 'Hello'
 
-        for command in &self.commands {
-            buffer_command.push_str("echo '---'\n");
+# command 2
+printf '%s\n' '---'
+printf '%s\n' '$ ls -la'
+ls -la
 
-            let mut lines = command
-                .lines
-                .iter()
-                .map(|line| str::replace(line, "\\", "\\\\"))
-                .map(|line| str::replace(line.as_str(), "'", "'\\''"));
-            if let Some(first_line) = lines.next() {
-                if first_line.contains('$') {
-                    buffer_command.push_str("# shellcheck disable=SC2016\n");
-                }
-                if first_line.ends_with("\\\\") {
-                    let without_backslash = &first_line[0..first_line.len() - 2];
-                    buffer_command.push_str(format!("echo '$ {without_backslash}'\\\\\n").as_str());
-                } else {
-                    buffer_command.push_str(format!("echo '$ {first_line}'\n").as_str());
-                }
+"#;
+            assert_eq!(expected, formatted);
+        }
 
-                for line in lines {
-                    if line.ends_with("\\\\") {
-                        let without_backslash = &line[0..line.len() - 2];
-                        buffer_command
-                            .push_str(format!("echo '> {without_backslash}'\\\\\n").as_str());
-                    } else {
-                        buffer_command.push_str(format!("echo '> {line}'\n").as_str());
-                    }
-                }
-            }
+        #[test]
+        fn format_as_shell_script_with_block_timeout() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Quick'"], vec!["sleep 1"]]);
+            commands.commands[1].timeout = Some(5);
+            let formatted = commands.as_shell_script();
+            assert!(formatted.contains("me_timeout() {"));
+            assert!(formatted.contains("\necho 'Quick'\n"));
+            assert!(formatted.contains("\nme_timeout 5 'sleep 1'\n"));
+        }
 
-            buffer_command.push_str(format!("{command}\n\n").as_str());
+        #[test]
+        fn format_as_shell_script_with_expected_status() {
+            let mut commands = of_multi_strs(vec![vec!["false"], vec!["exit 3"]]);
+            commands.commands[0].expected_status = Some(ExpectedStatus::Failure);
+            commands.commands[1].expected_status = Some(ExpectedStatus::Status(3));
+            let formatted = commands.as_shell_script();
+            assert!(formatted.contains(
+                r#"printf '%s\n' '$ false'
+set +e
+(
+set -e
+false
+)
+me_status=$?
+set -e
+if [ "$me_status" -eq 0 ]; then
+  echo "Expected the command to fail, but it succeeded" >&2
+  false
+fi
+"#
+            ));
+            assert!(formatted.contains(
+                r#"printf '%s\n' '$ exit 3'
+set +e
+(
+set -e
+exit 3
+)
+me_status=$?
+set -e
+if [ "$me_status" -ne 3 ]; then
+  echo "Expected the command to exit with 3, but it exited with $me_status" >&2
+  false
+fi
+"#
+            ));
         }
 
-        buffer_command
-    }
-}
+        #[test]
+        fn format_as_shell_script_with_self_delete() {
+            let mut commands = of_strs(vec!["ls -la"]);
+            commands.script.self_delete = true;
+            let formatted = commands.as_shell_script();
+            assert!(formatted.contains("\nset -e\n\ntrap 'rm -f \"$0\"' EXIT\n\n"));
 
-impl Display for CommandBlocks<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for command in &self.commands {
-            writeln!(f, "{}", command)?;
+            commands.script.quiet_success = true;
+            let formatted = commands.as_shell_script();
+            assert!(
+                formatted.contains(r#"rm -f "$me_output"; rm -f "$0"; exit "$me_status"' EXIT"#)
+            );
+            assert_eq!(1, formatted.matches("trap ").count());
         }
-        Ok(())
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        #[test]
+        fn format_as_shell_script_without_preamble() {
+            let mut commands = of_multi_strs(vec![vec!["set -eu"], vec!["ls -la"]]);
+            commands.script.preamble = false;
+            let formatted = commands.as_shell_script();
+            let expected = r#"printf '%s\n' '---'
+printf '%s\n' '$ set -eu'
+set -eu
 
-    mod parser {
-        use super::*;
+printf '%s\n' '---'
+printf '%s\n' '$ ls -la'
+ls -la
 
-        #[test]
-        fn parse_empty_content() {
-            let content = "";
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_empty();
-            assert_eq!(expected, parsed);
+"#;
+            assert_eq!(expected, formatted);
+
+            commands.script.trace = true;
+            let formatted = commands.as_shell_script();
+            assert!(formatted.starts_with("set -x\n\nprintf '%s\\n' '---'\n"));
         }
 
         #[test]
-        fn parse_content_without_commands() {
-            let content = r#"# README
+        fn format_as_shell_script_with_echo_comments() {
+            let mut commands = of_strs(vec![
+                "# Create the 'build' directory",
+                "mkdir build",
+                "  # Write the file",
+                "cat << EOF > build/notes.txt",
+                "# Kept within the file",
+                "EOF",
+                "ls \\",
+                "# Not a comment of its own",
+            ]);
+            let inert = commands.as_shell_script();
+            assert!(inert.contains(
+                r#"# Create the 'build' directory
+mkdir build
+  # Write the file
+cat << EOF > build/notes.txt
+# Kept within the file
+EOF
+ls \
+# Not a comment of its own
+"#
+            ));
 
-No commands here!!
-"#;
+            commands.script.echo_comments = true;
+            let echoed = commands.as_shell_script();
+            assert!(echoed.contains(
+                r#"printf '%s\n' 'Create the '\''build'\'' directory'
+mkdir build
+  printf '%s\n' 'Write the file'
+cat << EOF > build/notes.txt
+# Kept within the file
+EOF
+ls \
+# Not a comment of its own
+"#
+            ));
+            assert!(echoed.contains("printf '%s\\n' '$ # Create the '\\''build'\\'' directory'\n"));
+        }
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_empty();
-            assert_eq!(expected, parsed);
+        #[test]
+        fn format_as_shell_script_with_trace() {
+            let mut commands = of_strs(vec!["ls -la"]);
+            assert!(!commands.as_shell_script().contains("set -x"));
+
+            commands.script.trace = true;
+            let formatted = commands.as_shell_script();
+            assert!(formatted.contains("\nset -e\nset -x\n\n"));
         }
 
         #[test]
-        fn parse_content_with_one_single_line_command() {
-            let content = r#"# README
+        fn format_as_powershell_script() {
+            let commands = of_multi_strs(vec![vec!["echo 'Hello'"], vec!["dir", "  -Force"]]);
+            let formatted = commands.as_powershell_script();
+            let expected = r#"# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-Before command
+$ErrorActionPreference = 'Stop'
 
-```shell
-ls -la
-```
+Write-Output '---'
+Write-Output '$ echo ''Hello'''
+echo 'Hello'
+if ($LASTEXITCODE) { exit $LASTEXITCODE }
 
-After command
-"#;
+Write-Output '---'
+Write-Output '$ dir'
+Write-Output '>   -Force'
+dir
+  -Force
+if ($LASTEXITCODE) { exit $LASTEXITCODE }
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec!["ls -la"]);
-            assert_eq!(expected, parsed);
+"#;
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_with_multiple_single_line_command() {
-            let content = r#"# README
+        fn format_as_powershell_script_with_expected_status() {
+            let mut commands = of_multi_strs(vec![vec!["cmd /c exit 1"], vec!["cmd /c exit 3"]]);
+            commands.script.separator = None;
+            commands.commands[0].expected_status = Some(ExpectedStatus::Failure);
+            commands.commands[1].expected_status = Some(ExpectedStatus::Status(3));
+            let formatted = commands.as_powershell_script();
+            let expected = r#"# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-```shell
-echo "Hello"
-```
+$ErrorActionPreference = 'Stop'
 
-```shell
-ls -la
-```
+Write-Output '$ cmd /c exit 1'
+cmd /c exit 1
+if (-not $LASTEXITCODE) {
+  [Console]::Error.WriteLine('Expected the command to fail, but it succeeded')
+  exit 1
+}
 
-```shell
-echo "Goodbye"
-```
-"#;
+Write-Output '$ cmd /c exit 3'
+cmd /c exit 3
+if ($LASTEXITCODE -ne 3) {
+  [Console]::Error.WriteLine("Expected the command to exit with 3, but it exited with $LASTEXITCODE")
+  exit 1
+}
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_multi_strs(vec![
-                vec!["echo \"Hello\""],
-                vec!["ls -la"],
-                vec!["echo \"Goodbye\""],
-            ]);
-            assert_eq!(expected, parsed);
+"#;
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_with_different_indentation() {
-            let content = r#"# README
+        fn format_as_shell_script_with_separator() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Hello'"], vec!["ls -la"]]);
+            commands.script.separator = Some("=== 'Next' ===".to_string());
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
-```shell
-echo "Hello"
-```
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-- `ls` command
+set -e
 
-  ```shell
-  ls -la
-  ```
+printf '%s\n' '=== '\''Next'\'' ==='
+printf '%s\n' '$ echo '\''Hello'\'''
+echo 'Hello'
 
-  1. `echo` command
+printf '%s\n' '=== '\''Next'\'' ==='
+printf '%s\n' '$ ls -la'
+ls -la
 
-     ```shell
-     echo "Goodbye"
-     ```
 "#;
-
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_multi_strs(vec![
-                vec!["echo \"Hello\""],
-                vec!["ls -la"],
-                vec!["echo \"Goodbye\""],
-            ]);
-            assert_eq!(expected, parsed);
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_with_one_multi_line_command_backslash() {
-            let content = r#"# README
+        fn format_as_shell_script_with_delay_between_commands() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Hello'"], vec!["ls -la"]]);
+            commands.script.execution_mode = ExecutionMode::DelayBetweenCommands { delay_ms: 1500 };
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Hello'\'''
+echo 'Hello'
+
+sleep 1.500
+
+printf '%s\n' '---'
+printf '%s\n' '$ ls -la'
+ls -la
 
-```shell
-java \
-  -jar target/app.jar
-```
 "#;
+            assert_eq!(expected, formatted);
+        }
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec!["java \\", "  -jar target/app.jar"]);
-            assert_eq!(expected, parsed);
+        #[test]
+        fn format_as_shell_script_with_shebang() {
+            let content = "```shell\nls -la\n```\n";
+            for shebang in ["#!/usr/bin/env bash", "/usr/bin/env bash"] {
+                let options = Options::new(content).with_shebang(Some(shebang));
+                let formatted = options.build().as_shell_script();
+                assert!(formatted.starts_with("#!/usr/bin/env bash\n\n# Generated by"));
+            }
         }
 
         #[test]
-        fn parse_content_with_one_multi_line_command_here_document_without_indentation() {
-            let content = r#"# README
+        fn format_as_shell_script_with_tap() {
+            let mut commands = of_multi_strs(vec![vec!["cd docs # Docs"], vec!["ls -la"]]);
+            commands.script.separator = None;
+            commands.script.tap = true;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
-```shell
-patch -p1 -u './Test.java' << EOF
---- ./Test.java
-+++ ./Test.java
-@@ -1,3 +1,2 @@
- package demo;
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+exec 5>&1 1>&2
+me_test=0
+me_failed=0
+
+printf '%s\n' '$ cd docs # Docs'
+me_test=$((me_test + 1))
+set +e
+(
+set -e
+cd docs # Docs
+)
+me_status=$?
+set -e
+if [ "$me_status" -eq 0 ]; then
+  printf 'ok %d - %s\n' "$me_test" 'cd docs \# Docs' >&5
+else
+  printf 'not ok %d - %s\n' "$me_test" 'cd docs \# Docs' >&5
+  me_failed=1
+fi
 
- -import java.io.Console;
-EOF
-```
-"#;
+printf '%s\n' '$ ls -la'
+me_test=$((me_test + 1))
+set +e
+(
+set -e
+ls -la
+)
+me_status=$?
+set -e
+if [ "$me_status" -eq 0 ]; then
+  printf 'ok %d - %s\n' "$me_test" 'ls -la' >&5
+else
+  printf 'not ok %d - %s\n' "$me_test" 'ls -la' >&5
+  me_failed=1
+fi
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec![
-                "patch -p1 -u './Test.java' << EOF",
-                "--- ./Test.java",
-                "+++ ./Test.java",
-                "@@ -1,3 +1,2 @@",
-                " package demo;",
-                "",
-                " -import java.io.Console;",
-                "EOF",
-            ]);
-            assert_eq!(expected, parsed);
+echo "1..$me_test" >&5
+exit "$me_failed"
+"#;
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_with_one_multi_line_command_here_document_with_indentation() {
-            let content = r#"# README
+        fn format_as_shell_script_with_parallel() {
+            let mut commands =
+                of_multi_strs(vec![vec!["make lint"], vec!["make test"], vec!["ls"]]);
+            commands.script.separator = None;
+            commands.script.execution_mode = ExecutionMode::Parallel { jobs: 2 };
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
-- Step 1
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-  ```shell
-  patch -p1 -u './Test.java' << EOF
-  --- ./Test.java
-  +++ ./Test.java
-  @@ -1,3 +1,2 @@
-   package demo;
+set -e
 
-   -import java.io.Console;
-  EOF
-  ```
-"#;
+me_pids=""
+me_wait() {
+  me_failed=0
+  for me_pid in $me_pids; do
+    wait "$me_pid" || me_failed=1
+  done
+  me_pids=""
+  return "$me_failed"
+}
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec![
-                "patch -p1 -u './Test.java' << EOF",
-                "--- ./Test.java",
-                "+++ ./Test.java",
-                "@@ -1,3 +1,2 @@",
-                " package demo;",
-                "",
-                " -import java.io.Console;",
-                "EOF",
-            ]);
-            assert_eq!(expected, parsed);
-        }
+printf '%s\n' '$ make lint'
+(
+make lint
+) &
+me_pids="$me_pids $!"
 
-        #[test]
-        fn parse_content_with_one_multi_line_command_block() {
-            let content = r#"# README
+printf '%s\n' '$ make test'
+(
+make test
+) &
+me_pids="$me_pids $!"
+me_wait
 
-```shell
-while [ "$(curl --silent --output /dev/null --write-out '%{http_code}' 'http://localhost:8080')" -ne '200' ]
-do
-  echo 'Waiting for the application to start'
-  sleep 1
-done
-```
-"#;
+printf '%s\n' '$ ls'
+(
+ls
+) &
+me_pids="$me_pids $!"
+me_wait
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec![
-                "while [ \"$(curl --silent --output /dev/null --write-out '%{http_code}' 'http://localhost:8080')\" -ne '200' ]",
-                "do",
-                "  echo 'Waiting for the application to start'",
-                "  sleep 1",
-                "done",
-            ]);
-            assert_eq!(expected, parsed);
+"#;
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_with_multiple_single_line_commands() {
-            let content = r#"# README
-
-```shell
-echo "Line 1"
-echo "Line 2"
-echo "Line 3"
-```
-"#;
+        fn format_as_shell_script_with_strict() {
+            let mut commands = of_strs(vec!["ls -la"]);
+            commands.script.strict = true;
+            let formatted = commands.as_shell_script();
+            assert!(formatted.starts_with("#!/bin/sh\n"));
+            assert!(formatted.contains("\nset -e\nset -u\n\n"));
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec![
-                "echo \"Line 1\"",
-                "echo \"Line 2\"",
-                "echo \"Line 3\"",
-            ]);
-            assert_eq!(expected, parsed);
+            commands.script.shebang = Some("#!/usr/bin/env bash".to_string());
+            let formatted = commands.as_shell_script();
+            assert!(formatted.starts_with("#!/usr/bin/env bash\n"));
+            assert!(formatted.contains("\nset -e\nset -u\nset -o pipefail\n\n"));
         }
 
         #[test]
-        fn parse_content_with_multiple_multi_line_commands() {
-            let content = r#"# README
+        fn format_as_shell_script_with_isolate() {
+            let mut commands = of_multi_strs(vec![vec!["cd /tmp"], vec!["ls -la"]]);
+            commands.script.isolate = true;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
-```shell
-echo "Before"
-java \
-  -jar target/app-1.jar
-java \
-  -jar target/app-2.jar
-echo "After"
-```
-"#;
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-            let options = Options::new(content);
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec![
-                "echo \"Before\"",
-                "java \\",
-                "  -jar target/app-1.jar",
-                "java \\",
-                "  -jar target/app-2.jar",
-                "echo \"After\"",
-            ]);
-            assert_eq!(expected, parsed);
+set -e
+
+printf '%s\n' '---'
+printf '%s\n' '$ cd /tmp'
+(
+cd /tmp
+)
+
+printf '%s\n' '---'
+printf '%s\n' '$ ls -la'
+(
+ls -la
+)
+
+"#;
+            assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn parse_content_skip_commands() {
-            let content = r#"# README
-
-```shell
-echo "Line 1"
-```
+        fn format_as_shell_script_with_sudo() {
+            let mut commands =
+                of_multi_strs(vec![vec!["apt-get install \\", "  curl"], vec!["ls"]]);
+            commands.commands[0].sudo = vec![0];
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
 
-```shell
-echo "Hello there"
-```
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
 
-```shell
-echo "Line 2"
-echo "Hello there"
-```
-"#;
+set -e
 
-            let skip_commands = Regex::new(r"Line \d").expect("Invalid skip commands regex");
-            let options = Options::new(content).with_skip_commands(Some(&skip_commands));
-            let parsed = CommandBlocks::parse(&options);
-            let expected = ok_of_strs(vec!["echo \"Hello there\""]);
-            assert_eq!(expected, parsed);
-        }
-    }
+printf '%s\n' '---'
+printf '%s\n' '$ sudo apt-get install \'
+printf '%s\n' '>   curl'
+sudo apt-get install \
+  curl
 
-    mod formatter {
-        use super::*;
+printf '%s\n' '---'
+printf '%s\n' '$ ls'
+ls
 
-        #[test]
-        fn format_empty_command() {
-            let commands = empty();
-            let formatted = format!("{}", commands);
-            let expected = "";
+"#;
             assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn format_one_single_line_command() {
-            let commands = of_strs(vec!["ls -la"]);
-            let formatted = format!("{}", commands);
-            let expected = r#"ls -la
+        fn format_as_shell_script_with_title() {
+            let mut commands = of_strs(vec!["make install"]);
+            commands.commands[0].title = Some("Owner's step");
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '%s\n' '---'
+printf '%s\n' '=== Owner'\''s step ==='
+printf '%s\n' '$ make install'
+make install
+
 "#;
             assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn format_multiple_single_line_command() {
-            let commands = of_strs(vec!["echo \"Hello\"", "ls -la", "echo \"Goodbye\""]);
-            let formatted = format!("{}", commands);
-            let expected = r#"echo "Hello"
+        fn format_as_shell_script_without_separator() {
+            let mut commands = of_strs(vec!["ls -la"]);
+            commands.script.separator = None;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '%s\n' '$ ls -la'
 ls -la
-echo "Goodbye"
+
 "#;
             assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn format_one_multi_line_command() {
-            let commands = of_strs(vec!["java \\", " -jar target/app.jar"]);
-            let formatted = format!("{}", commands);
-            let expected = r#"java \
- -jar target/app.jar
+        fn format_as_shell_script_with_prefix_output() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Hello'"], vec!["ls -la"]]);
+            commands.script.prefix_output = true;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+printf '\036%d\n' 1
+printf '\036%d\n' 1 >&2
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Hello'\'''
+echo 'Hello'
+
+printf '\036%d\n' 2
+printf '\036%d\n' 2 >&2
+printf '%s\n' '---'
+printf '%s\n' '$ ls -la'
+ls -la
+
 "#;
             assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn format_multiple_single_line_commands() {
-            let commands = of_strs(vec![
-                "echo \"Line 1\"",
-                "echo \"Line 2\"",
-                "echo \"Line 3\"",
-            ]);
-            let formatted = format!("{}", commands);
-            let expected = r#"echo "Line 1"
-echo "Line 2"
-echo "Line 3"
+        fn format_as_shell_script_with_timeout() {
+            let mut commands = of_strs(vec!["echo 'Waiting'", "sleep 1"]);
+            commands.script.timeout = Some(30);
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+me_timeout() {
+  if command -v timeout >/dev/null 2>&1; then
+    me_status=0
+    timeout "$1" /bin/sh -ec "$2" || me_status=$?
+    if [ "$me_status" -eq 124 ]; then
+      echo "Command timed out after $1 seconds" >&2
+    fi
+    return "$me_status"
+  fi
+
+  eval "$2"
+}
+
+if ! command -v timeout >/dev/null 2>&1; then
+  echo "The timeout utility is missing, running the commands without a timeout" >&2
+fi
+
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Waiting'\'''
+printf '%s\n' '> sleep 1'
+me_timeout 30 'echo '\''Waiting'\''
+sleep 1'
+
 "#;
             assert_eq!(expected, formatted);
+
+            commands.script.shebang = Some("#!/usr/bin/env bash".to_string());
+            let formatted = commands.as_shell_script();
+            assert!(formatted
+                .contains("    timeout \"$1\" /usr/bin/env bash -ec \"$2\" || me_status=$?\n"));
         }
 
         #[test]
-        fn format_multiple_multi_line_commands() {
-            let commands = of_multi_strs(vec![
-                vec!["echo \"Before\""],
-                vec!["java \\", " -jar target/app-1.jar"],
-                vec!["java \\", " -jar target/app-2.jar"],
-                vec!["echo \"After\""],
-            ]);
+        fn format_as_shell_script_with_quiet_success() {
+            let mut commands = of_multi_strs(vec![vec!["echo 'Passing'"], vec!["false"]]);
+            commands.script.quiet_success = true;
+            let formatted = commands.as_shell_script();
+            let expected = r#"#!/bin/sh
+
+# Generated by the MARKDOWN executor
+# This file is automatically deleted once the execution completes
+
+set -e
+
+me_output="$(mktemp)"
+exec 3>&1 4>&2
+trap 'me_status=$?; exec 1>&3 2>&4; [ "$me_status" -eq 0 ] || cat "$me_output"; rm -f "$me_output"; exit "$me_status"' EXIT
+
+exec >"$me_output" 2>&1
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Passing'\'''
+echo 'Passing'
+
+exec >"$me_output" 2>&1
+printf '%s\n' '---'
+printf '%s\n' '$ false'
+false
 
-            let formatted = format!("{}", commands);
-            let expected = r#"echo "Before"
-java \
- -jar target/app-1.jar
-java \
- -jar target/app-2.jar
-echo "After"
 "#;
             assert_eq!(expected, formatted);
         }
 
         #[test]
-        fn format_as_shell_script() {
-            let commands = of_strs(vec!["java \\", " -XshowSettings:vm \\", " --version"]);
+        fn format_as_shell_script_with_repeat() {
+            let mut commands = of_strs(vec!["echo 'Hello'"]);
+            commands.script.repeat = 2;
             let formatted = commands.as_shell_script();
             let expected = r#"#!/bin/sh
 
@@ -571,14 +4619,26 @@ echo "After"
 
 set -e
 
-echo '---'
-echo '$ java '\\
-echo '>  -XshowSettings:vm '\\
-echo '>  --version'
-java \
- -XshowSettings:vm \
- --version
+me_millis() {
+  me_nanos="$(date +%s%N)"
+  case "$me_nanos" in
+    *[!0-9]*) echo "$(($(date +%s) * 1000))" ;;
+    *) echo "$((me_nanos / 1000000))" ;;
+  esac
+}
+
+me_iteration=1
+while [ "$me_iteration" -le 2 ]; do
+me_started="$(me_millis)"
 
+printf '%s\n' '---'
+printf '%s\n' '$ echo '\''Hello'\'''
+echo 'Hello'
+
+me_elapsed=$(($(me_millis) - me_started))
+printf 'Iteration %d took %d.%03d seconds\n' "$me_iteration" $((me_elapsed / 1000)) $((me_elapsed % 1000)) >&2
+me_iteration=$((me_iteration + 1))
+done
 "#;
             assert_eq!(expected, formatted);
         }
@@ -589,32 +4649,82 @@ java \
     }
 
     fn empty() -> CommandBlocks<'static> {
-        CommandBlocks { commands: vec![] }
+        CommandBlocks {
+            commands: vec![],
+            script: ScriptOptions::default(),
+            skipped: 0,
+            found: 0,
+            execute_from_line: None,
+            execute_until_line: None,
+        }
     }
 
-    fn ok_of_strs(single_block_commands: Vec<&str>) -> Result<CommandBlocks<'_>, ParserError> {
-        Ok(of_strs(single_block_commands))
+    fn ok_of_strs(
+        single_block_commands: Vec<&str>,
+        line_number: usize,
+    ) -> Result<CommandBlocks<'_>, ParserError> {
+        Ok(at_line_numbers(
+            of_strs(single_block_commands),
+            &[line_number],
+        ))
     }
 
     fn of_strs(single_block_commands: Vec<&str>) -> CommandBlocks<'_> {
         CommandBlocks {
-            commands: vec![CommandBlock {
-                lines: single_block_commands,
-            }],
+            commands: vec![CommandBlock::new(single_block_commands)],
+            script: ScriptOptions::default(),
+            skipped: 0,
+            found: 1,
+            execute_from_line: None,
+            execute_until_line: None,
         }
     }
 
-    fn ok_of_multi_strs(
-        multi_blocks_commands: Vec<Vec<&str>>,
-    ) -> Result<CommandBlocks<'_>, ParserError> {
-        Ok(of_multi_strs(multi_blocks_commands))
+    fn ok_of_multi_strs<'a>(
+        multi_blocks_commands: Vec<Vec<&'a str>>,
+        line_numbers: &[usize],
+    ) -> Result<CommandBlocks<'a>, ParserError> {
+        Ok(at_line_numbers(
+            of_multi_strs(multi_blocks_commands),
+            line_numbers,
+        ))
+    }
+
+    fn between_lines<'a>(
+        commands: Result<CommandBlocks<'a>, ParserError>,
+        execute_from_line: Option<(usize, &'a str)>,
+        execute_until_line: Option<(usize, &'a str)>,
+    ) -> Result<CommandBlocks<'a>, ParserError> {
+        commands.map(|mut commands| {
+            commands.execute_from_line = execute_from_line;
+            commands.execute_until_line = execute_until_line;
+            commands
+        })
+    }
+
+    fn at_line_numbers<'a>(
+        mut commands: CommandBlocks<'a>,
+        line_numbers: &[usize],
+    ) -> CommandBlocks<'a> {
+        assert_eq!(commands.commands.len(), line_numbers.len());
+        for (command, line_number) in commands.commands.iter_mut().zip(line_numbers) {
+            command.line_number = Some(*line_number);
+        }
+        commands
     }
 
     fn of_multi_strs(multi_blocks_commands: Vec<Vec<&str>>) -> CommandBlocks<'_> {
         let commands = multi_blocks_commands
             .into_iter()
-            .map(|lines| CommandBlock { lines })
-            .collect();
-        CommandBlocks { commands }
+            .map(CommandBlock::new)
+            .collect::<Vec<_>>();
+        CommandBlocks {
+            found: commands.len(),
+            commands,
+            script: ScriptOptions::default(),
+            skipped: 0,
+            execute_from_line: None,
+            execute_until_line: None,
+        }
     }
 }
@@ -0,0 +1,79 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The default options read from a TOML file, named `.me.toml` in the current directory unless
+/// another file is provided using `--config`.  Options provided on the command line take
+/// precedence over those read from this file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) file_name: Option<String>,
+    pub(crate) tag: Option<String>,
+    pub(crate) languages: Option<Vec<String>>,
+    pub(crate) prompts: Option<Vec<String>>,
+    pub(crate) skip_commands: Option<String>,
+    pub(crate) recursive: Option<usize>,
+    pub(crate) no_ignore: Option<bool>,
+    pub(crate) exclude: Option<Vec<String>>,
+    pub(crate) repeat: Option<u16>,
+    pub(crate) retry: Option<u32>,
+    pub(crate) retry_delay: Option<u32>,
+    pub(crate) delay: Option<String>,
+    pub(crate) parallel: Option<u32>,
+    pub(crate) timeout: Option<u32>,
+    pub(crate) quiet_success: Option<bool>,
+    pub(crate) annotate: Option<bool>,
+    pub(crate) trace: Option<bool>,
+    pub(crate) shell: Option<PathBuf>,
+    pub(crate) isolate: Option<bool>,
+    pub(crate) strict: Option<bool>,
+    pub(crate) separator: Option<String>,
+    pub(crate) files: Option<Vec<FileConfig>>,
+}
+
+/// The options that apply to the MARKDOWN files whose path, relative to the current directory,
+/// matches the glob pattern, such as `docs/**/*.md`, written as a `[[files]]` table.  The first
+/// matching table applies.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileConfig {
+    pub(crate) path: String,
+    pub(crate) skip_commands: Option<String>,
+}
+
+impl Config {
+    /// The configuration file read from the current directory, when found
+    pub(crate) const FILE_NAME: &'static str = ".me.toml";
+
+    /// Reads the configuration from the given file, which must exist, or from the `.me.toml` file
+    /// in the current directory, if one exists.
+    pub(crate) fn load(path: Option<&Path>) -> Result<Option<Self>, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = PathBuf::from(Self::FILE_NAME);
+                if !path.is_file() {
+                    return Ok(None);
+                }
+                path
+            }
+        };
+
+        let content = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "failed to read the configuration file `{}`: {}",
+                path.display(),
+                e
+            )
+        })?;
+        toml::from_str(&content).map(Some).map_err(|e| {
+            format!(
+                "failed to parse the configuration file `{}`: {}",
+                path.display(),
+                e
+            )
+        })
+    }
+}
@@ -0,0 +1,16 @@
+#![warn(missing_debug_implementations, rust_2018_idioms)]
+
+//! Parses the shell code blocks of MARKDOWN files, such as `README.md`, and runs their commands.
+//!
+//! The public surface considered stable is [`Options`], used to configure the parser,
+//! [`CommandBlocks`] and [`CommandBlock`], holding the parsed commands, [`ExpectedStatus`],
+//! [`ParserError`] and [`ExecutionMode`].
+//! [`ShellScript`] is public so that the `me` binary can run the commands, and may change.
+
+pub use crate::command::{
+    CommandBlock, CommandBlocks, ExecutionMode, ExpectedStatus, Options, ParserError,
+};
+pub use crate::shell::{CapturedOutput, ShellScript};
+
+mod command;
+mod shell;
@@ -1,23 +1,353 @@
 #![warn(missing_debug_implementations, rust_2018_idioms)]
 
-use crate::cla::Args;
-use crate::command::Options;
-use crate::shell::ShellScript;
+use std::{io, process};
+
+use me::{Options, ParserError, ShellScript};
+
+use crate::cla::{Args, ErrorFormat, ErrorReport, Format, MarkdownFile};
 
 mod cla;
-mod command;
-mod shell;
+mod config;
 
 fn main() {
     let args = Args::create();
+    if args.explain() {
+        print!("{}", args.explanation());
+        return;
+    }
+
+    if args.print_files() {
+        for markdown in args.files() {
+            println!("{}", markdown);
+        }
+        return;
+    }
+
+    let shebang = args.shebang();
+    let shell = args.shell();
+    let mut skipped = 0;
+    let mut counted = 0;
+    let mut failed = vec![];
+
+    let files = args.files();
+    let total = files.len();
+    for (index, markdown) in files.into_iter().enumerate() {
+        let content = match markdown.read() {
+            Ok(content) => content,
+            Err(e) if args.allow_missing_file() => {
+                if args.error_format() == ErrorFormat::Json {
+                    ErrorReport {
+                        file: Some(markdown.to_string()),
+                        kind: "skipped",
+                        message: e.to_string(),
+                        line: None,
+                    }
+                    .print();
+                } else {
+                    eprintln!("Warning: skipping the MARKDOWN file {}: {}", markdown, e);
+                }
+                continue;
+            }
+            Err(e) => {
+                report(&args, &markdown, Failure::Read(e));
+                failed.push(markdown.to_string());
+                if args.fail_fast() {
+                    break;
+                }
+                continue;
+            }
+        };
+        let options = Options::new(&content)
+            .with_languages(args.languages())
+            .with_prompts(args.prompts())
+            .with_prompt_without_space(args.prompt_without_space())
+            .with_skip_commands(args.skip_commands_for(&markdown))
+            .with_deny(args.deny())
+            .with_sudo_match(args.sudo_match())
+            .with_tag(args.tag())
+            .with_group(args.group())
+            .with_section(args.section())
+            .with_execute_from(args.execute_from())
+            .with_execute_until(args.execute_until())
+            .with_execute_from_prefix(args.execute_from_prefix())
+            .with_execute_until_prefix(args.execute_until_prefix())
+            .with_skip_first(args.skip_first())
+            .with_first_only(args.first_only())
+            .with_shuffle(args.shuffle())
+            .with_prepend(args.prepend())
+            .with_append(args.append())
+            .with_repeat(args.repeat())
+            .with_execution_mode(args.execution_mode())
+            .with_quiet_success(args.quiet_success())
+            .with_echo_comments(args.echo_comments())
+            .with_timeout(args.timeout())
+            .with_annotate(args.annotate())
+            .with_separator(args.separator())
+            .with_trace(args.trace())
+            .with_tap(args.tap())
+            .with_shebang(shebang.as_deref())
+            .with_preamble(!args.no_preamble())
+            .with_parser_dump(args.dump_ast().then_some(dump_parser_state))
+            .with_isolate(args.isolate())
+            .with_strict(args.strict())
+            .with_self_delete(args.no_exec_format().is_none() && args.output().is_none())
+            .with_prefix_output(args.prefix_output());
+        let commands = if args.is_from_list() {
+            options.try_build_from_list()
+        } else {
+            options.try_build()
+        };
+        let commands = match commands {
+            Ok(commands) => commands,
+            Err(e) => {
+                report(&args, &markdown, Failure::Parse(e));
+                failed.push(markdown.to_string());
+                if args.fail_fast() {
+                    break;
+                }
+                continue;
+            }
+        };
+        skipped += commands.skipped();
+
+        if args.verbose() {
+            let matched = [
+                ("from", commands.execute_from_line()),
+                ("until", commands.execute_until_line()),
+            ];
+            for (boundary, (line_number, line)) in matched
+                .into_iter()
+                .filter_map(|(boundary, line)| line.map(|line| (boundary, line)))
+            {
+                eprintln!(
+                    "Executing {} line {} of {}: {}",
+                    boundary, line_number, markdown, line
+                );
+            }
+        }
+
+        if args.count() {
+            println!("{}: {}", markdown, commands.found());
+            counted += commands.found();
+            continue;
+        }
+
+        if args.require_commands() && commands.found() == 0 {
+            report(&args, &markdown, Failure::NoCommands);
+            failed.push(markdown.to_string());
+            if args.fail_fast() {
+                break;
+            }
+            continue;
+        }
+
+        if args.check() {
+            let script = match create_shell_script(&args, &markdown, &commands.as_script()) {
+                Ok(script) => script,
+                Err(e) => {
+                    report(&args, &markdown, Failure::Script(e));
+                    failed.push(markdown.to_string());
+                    if args.fail_fast() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let output = script.with_shell(shell.as_deref()).check();
+            if !output.status.success() {
+                report(&args, &markdown, Failure::Syntax(output.stderr));
+                failed.push(markdown.to_string());
+                if args.fail_fast() {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        match args.no_exec_format() {
+            Some(Format::Script) if args.output().is_some() => {
+                if let Err(e) = create_shell_script(&args, &markdown, &commands.as_script()) {
+                    report(&args, &markdown, Failure::Script(e));
+                    failed.push(markdown.to_string());
+                    if args.fail_fast() {
+                        break;
+                    }
+                }
+            }
+            Some(Format::Script) => print!("{}", commands.as_script()),
+            Some(Format::List) => print!("{}", commands.as_list()),
+            Some(Format::Json) => println!("{}", commands.as_json()),
+            Some(Format::Tap) => unreachable!("The tap format runs the commands"),
+            None => {
+                let working_dir = match args.working_dir() {
+                    Some(working_dir) => Some(working_dir),
+                    None => match markdown.working_dir(&content) {
+                        Ok(working_dir) => working_dir,
+                        Err(e) => {
+                            report(&args, &markdown, Failure::WorkingDir(e));
+                            failed.push(markdown.to_string());
+                            if args.fail_fast() {
+                                break;
+                            }
+                            continue;
+                        }
+                    },
+                };
+
+                if args.progress() {
+                    eprintln!("[{}/{}] Running {}", index + 1, total, markdown);
+                }
+
+                let script = match create_shell_script(&args, &markdown, &commands.as_script()) {
+                    Ok(script) => script,
+                    Err(e) => {
+                        report(&args, &markdown, Failure::Script(e));
+                        failed.push(markdown.to_string());
+                        if args.fail_fast() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let succeeded = script
+                    .with_working_dir(working_dir)
+                    .with_envs(args.envs())
+                    .with_shell(shell.as_deref())
+                    .with_log(args.log().then(|| markdown.log_path()))
+                    .with_prefix_output(args.prefix_output())
+                    .run();
+
+                if ShellScript::interrupted() {
+                    report(&args, &markdown, Failure::Interrupted);
+                    process::exit(130);
+                }
+
+                if !succeeded {
+                    report(&args, &markdown, Failure::Run);
+                    failed.push(markdown.to_string());
+                    if args.fail_fast() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    if args.count() {
+        println!("total: {}", counted);
+    }
+
+    if let Some(skip_commands) = args.skip_commands() {
+        if skipped == 0 {
+            let message = format!(
+                "no commands were skipped as none matched the skip commands regex '{}'",
+                skip_commands
+            );
+            if args.error_format() == ErrorFormat::Json {
+                ErrorReport {
+                    file: None,
+                    kind: "no-skipped-commands",
+                    message,
+                    line: None,
+                }
+                .print();
+            } else {
+                eprintln!("Warning: {}", message);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        if args.error_format() == ErrorFormat::Text {
+            eprintln!("The commands failed in the following files:");
+            for file in &failed {
+                eprintln!("  {}", file);
+            }
+        }
+        process::exit(1);
+    }
+}
 
-    for markdown in args.files() {
-        let shell_script = Options::new(&markdown.read())
-            .with_skip_commands(args.skip_commands())
-            .build()
-            .as_shell_script();
+/// The reason the commands of a MARKDOWN file did not run successfully.
+#[derive(Debug)]
+enum Failure {
+    Read(io::Error),
+    Parse(ParserError),
+    NoCommands,
+    Syntax(String),
+    WorkingDir(io::Error),
+    Script(io::Error),
+    Run,
+    Interrupted,
+}
+
+/// Prints the parser's state after a line of the MARKDOWN file to the standard error, used by the
+/// `--dump-ast` developer tool.
+fn dump_parser_state(state: &str) {
+    eprintln!("{}", state);
+}
+
+/// Prints the failure to the standard error, as text or as a JSON object on a single line.  In
+/// text, the failing commands are not reported, as their output is printed and the files whose
+/// commands failed are listed once all files are run.
+fn report(args: &Args, markdown: &MarkdownFile, failure: Failure) {
+    if args.error_format() == ErrorFormat::Json {
+        let (kind, message, line) = match failure {
+            Failure::Read(e) => ("read", e.to_string(), None),
+            Failure::Parse(e) => ("parse", e.message().to_string(), e.line_number()),
+            Failure::NoCommands => ("no-commands", "No commands found".to_string(), None),
+            Failure::Syntax(errors) => ("syntax", errors.trim_end().to_string(), None),
+            Failure::WorkingDir(e) => ("workdir", e.to_string(), None),
+            Failure::Script(e) => ("script", e.to_string(), None),
+            Failure::Run => ("run", "The commands failed".to_string(), None),
+            Failure::Interrupted => ("interrupted", "Interrupted".to_string(), None),
+        };
+        ErrorReport {
+            file: Some(markdown.to_string()),
+            kind,
+            message,
+            line,
+        }
+        .print();
+        return;
+    }
+
+    match failure {
+        Failure::Read(e) => eprintln!("Failed to read the MARKDOWN file {}: {}", markdown, e),
+        Failure::Parse(e) => eprintln!("Failed to parse the MARKDOWN file {}: {}", markdown, e),
+        Failure::NoCommands => eprintln!("No commands found in {}", markdown),
+        Failure::Syntax(errors) => {
+            eprintln!("The commands of {} have syntax errors:", markdown);
+            eprint!("{}", errors);
+        }
+        Failure::WorkingDir(e) => {
+            eprintln!(
+                "Failed to resolve the working directory of {}: {}",
+                markdown, e
+            )
+        }
+        Failure::Script(e) => {
+            eprintln!("Failed to create the shell script for {}: {}", markdown, e)
+        }
+        Failure::Run => {}
+        Failure::Interrupted => {
+            eprintln!("Interrupted while running the commands in {}", markdown)
+        }
+    }
+}
 
-        ShellScript::new(&markdown.parent_dir(), &shell_script).run();
+/// Creates the shell script at the path provided by the `--output` option, or a temporary one in
+/// the MARKDOWN file's directory otherwise.
+fn create_shell_script(
+    args: &Args,
+    markdown: &MarkdownFile,
+    commands: &str,
+) -> io::Result<ShellScript> {
+    match args.output() {
+        Some(output) => ShellScript::new_at(output, &markdown.parent_dir(), commands),
+        None => {
+            ShellScript::new_with_prefix(&markdown.parent_dir(), args.script_prefix(), commands)
+        }
     }
 }
 
@@ -26,9 +356,15 @@ mod tests {
     use std::fs;
     use std::fs::File;
     use std::io::Write;
+    #[cfg(unix)]
+    use std::os::unix::process::CommandExt;
     use std::path::Path;
+    use std::process;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     use assert_cmd::Command;
+    use regex::Regex;
 
     #[test]
     fn run_with_no_args() {
@@ -46,9 +382,7 @@ echo 'Hello world!!'
 "#,
         );
 
-        Command::cargo_bin("../release/me")
-            .expect("Failed to create test command")
-            .current_dir(dir)
+        me().current_dir(dir)
             .assert()
             .stdout(
                 r#"---
@@ -85,12 +419,10 @@ echo 'Hello 4!!'
 "#,
         );
 
-        Command::cargo_bin("../release/me")
-            .expect("Failed to create test command")
-            .current_dir(dir)
+        me().current_dir(dir)
             .args(["--skip-commands", "Line \\d+"])
             .assert()
-            .stdout(format!(
+            .stdout(
                 r#"---
 $ echo 'Hello 1!!'
 Hello 1!!
@@ -98,57 +430,2185 @@ Hello 1!!
 $ echo 'Hello 4!!'
 Hello 4!!
 "#
-            ))
+                .to_string(),
+            )
             .success();
     }
 
     #[test]
-    fn run_with_recursive_args() {
-        let dir = "./target/fixtures/run_with_recursive_args";
+    fn run_with_unmatched_skip_commands_args() {
+        let dir = "./target/fixtures/run_with_unmatched_skip_commands_args";
         remove_fixtures(dir);
         new_fixture(
             &format!("{}/README.md", dir),
             r#"# README Fixture
 ```shell
-echo 'Level 1'
+echo 'Hello'
 ```
 "#,
         );
 
+        me()
+            .current_dir(dir)
+            .args(["--skip-commands", "Typo"])
+            .assert()
+            .stdout("---\n$ echo 'Hello'\nHello\n")
+            .stderr(
+                "Warning: no commands were skipped as none matched the skip commands regex 'Typo'\n",
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_require_commands_args() {
+        let dir = "./target/fixtures/run_with_require_commands_args";
+        remove_fixtures(dir);
         new_fixture(
-            &format!("{}/a/README.md", dir),
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```python
+print('Hello')
+```
+"#,
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--require-commands"])
+            .output()
+            .expect("Failed to run the command");
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut lines = stderr.lines();
+        assert!(lines.next().unwrap().starts_with("No commands found in "));
+        assert_eq!(
+            Some("The commands failed in the following files:"),
+            lines.next()
+        );
+    }
+
+    #[test]
+    fn run_with_delay_args() {
+        let dir = "./target/fixtures/run_with_delay_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
             r#"# README Fixture
 ```shell
-echo 'Level 2'
+echo 'Hello 1'
+```
+
+```shell
+echo 'Hello 2'
 ```
 "#,
         );
 
+        me().current_dir(dir)
+            .args(["--delay", "10ms"])
+            .assert()
+            .stdout("---\n$ echo 'Hello 1'\nHello 1\n---\n$ echo 'Hello 2'\nHello 2\n")
+            .success();
+
+        for delay in ["", "ms", "1h", "-1s", "99999999m"] {
+            me().current_dir(dir)
+                .args(["--delay", delay])
+                .assert()
+                .failure();
+        }
+
+        new_fixture(&format!("{}/.me.toml", dir), "retry = 2\n");
+        let output = run_with_args(dir, &["--explain", "--delay", "1s"]);
+        assert!(output.contains("\nexecution mode: wait 1000ms between commands\n"));
+    }
+
+    #[test]
+    fn run_with_parallel_args() {
+        let dir = "./target/fixtures/run_with_parallel_args";
+        remove_fixtures(dir);
         new_fixture(
-            &format!("{}/a/b/README.md", dir),
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+        new_fixture(&format!("{}/.me.toml", dir), "retry = 2\n");
+
+        let output = run_with_args(dir, &["--parallel", "2", "--no-separator"]);
+        assert_eq!("$ echo 'Hello'\nHello\n", output);
+
+        let output = run_with_args(dir, &["--explain", "--parallel", "2"]);
+        assert!(output.contains("\nexecution mode: parallel, up to 2 jobs\n"));
+
+        me().current_dir(dir)
+            .args(["--parallel", "2", "--quiet-success"])
+            .assert()
+            .failure();
+
+        new_fixture(&format!("{}/.me.toml", dir), "quiet_success = true\n");
+        let output = run_with_args(dir, &["--parallel", "2", "--no-separator"]);
+        assert_eq!("$ echo 'Hello'\nHello\n", output);
+
+        new_fixture(&format!("{}/.me.toml", dir), "parallel = 2\n");
+        let output = run_with_args(dir, &["--quiet-success", "--no-separator"]);
+        assert_eq!("", output);
+    }
+
+    #[test]
+    fn run_with_tap_format_args() {
+        let dir = "./target/fixtures/run_with_tap_format_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
             r#"# README Fixture
 ```shell
-echo 'Level 3'
+echo 'Hello'
+```
+
+```shell
+false
+echo 'After'
+```
+
+```shell
+echo 'World'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--format", "tap", "--no-separator"])
+            .assert()
+            .stdout("ok 1 - echo 'Hello'\nnot ok 2 - false\nok 3 - echo 'World'\n1..3\n")
+            .failure();
+
+        for args in [
+            vec!["--format", "tap", "--no-exec"],
+            vec!["--format", "tap", "--quiet-success"],
+            vec!["--format", "json"],
+        ] {
+            me().current_dir(dir).args(args).assert().failure();
+        }
+
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\nprintf '%s\\n' 'a\\b' # Path\n```\n",
+        );
+        new_fixture(&format!("{}/.me.toml", dir), "quiet_success = true\n");
+        for shell in ["/bin/sh", "/bin/bash"] {
+            me().current_dir(dir)
+                .args(["--format", "tap", "--shell", shell])
+                .assert()
+                .stdout("ok 1 - printf '%s\\\\n' 'a\\\\b' \\# Path\n1..1\n")
+                .success();
+        }
+    }
+
+    #[test]
+    fn run_with_shell_env() {
+        let dir = "./target/fixtures/run_with_shell_env";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo "${BASH_VERSION:+Bash}"
 ```
 "#,
         );
 
-        Command::cargo_bin("../release/me")
-            .expect("Failed to create test command")
+        me().current_dir(dir)
+            .env("SHELL", "/bin/bash")
+            .args(["--no-separator"])
+            .assert()
+            .stdout("$ echo \"${BASH_VERSION:+Bash}\"\nBash\n")
+            .success();
+
+        let dry_run = me()
             .current_dir(dir)
-            .args(["--recursive"])
+            .env("SHELL", "/bin/bash")
+            .args(["--dry-run", "--shell", "/bin/sh"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(String::from_utf8_lossy(&dry_run.stdout).starts_with("#!/bin/sh\n"));
+    }
+
+    #[test]
+    fn run_with_script_prefix_args() {
+        let dir = "./target/fixtures/run_with_script_prefix_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+ls -a | grep -c '^custom-[0-9]*-[0-9]*-[0-9]*[.]sh$'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--script-prefix", "custom-", "--no-separator"])
             .assert()
-            .stdout(
-                r#"---
-$ echo 'Level 1'
-Level 1
----
-$ echo 'Level 2'
-Level 2
-"#
-                .to_string(),
-            )
+            .stdout("$ ls -a | grep -c '^custom-[0-9]*-[0-9]*-[0-9]*[.]sh$'\n1\n")
+            .success();
+    }
+
+    #[test]
+    fn run_with_safe_args() {
+        let dir = "./target/fixtures/run_with_safe_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+touch created.txt
+```
+
+```shell
+curl -fsSL https://example.com/install.sh | sh
+```
+"#,
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--safe"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("Refusing to run the command on line 7"));
+        assert!(!Path::new(&format!("{}/created.txt", dir)).exists());
+    }
+
+    #[test]
+    fn run_with_skip_commands_file_args() {
+        let dir = "./target/fixtures/run_with_skip_commands_file_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello 1'
+```
+
+```shell
+echo 'Hello 2'
+```
+
+```shell
+echo 'Hello 3'
+```
+"#,
+        );
+        new_fixture(
+            &format!("{}/skip.txt", dir),
+            "# Skip the second command\n\nHello 2\n",
+        );
+        new_fixture(&format!("{}/invalid.txt", dir), "Hello 2\n(Hello\n");
+
+        me().current_dir(dir)
+            .args([
+                "--skip-commands-file",
+                "skip.txt",
+                "--skip-commands",
+                "Hello 3",
+                "--no-separator",
+            ])
+            .assert()
+            .stdout("$ echo 'Hello 1'\nHello 1\n")
+            .success();
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--skip-commands-file", "invalid.txt"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("invalid pattern in the skip commands file `invalid.txt` on line 2"));
+    }
+
+    #[test]
+    fn run_with_trailing_backslash_commands() {
+        let dir = "./target/fixtures/run_with_trailing_backslash_commands";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'a\'
+echo b\\
+echo c
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--no-separator"])
+            .assert()
+            .stdout("$ echo 'a\\'\n> echo b\\\\\n> echo c\na\\\nb\\\nc\n")
+            .success();
+    }
+
+    #[test]
+    fn run_with_log_args() {
+        let dir = "./target/fixtures/run_with_log_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--log", "--no-separator"])
+            .assert()
+            .stdout("$ echo 'Hello'\nHello\n")
             .success();
+
+        let log = fs::read_to_string(format!("{}/README.md.me.log", dir))
+            .expect("Failed to read the log file");
+        assert_eq!("$ echo 'Hello'\nHello\n", log);
+    }
+
+    #[test]
+    fn run_with_execute_from_and_until_args() {
+        let dir = "./target/fixtures/run_with_execute_from_and_until_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+$ echo 'Before'
+```
+
+```shell
+$ echo 'Hello'
+```
+
+```shell
+$ echo 'After'
+```
+"#,
+        );
+
+        let output = run_with_args(
+            dir,
+            &[
+                "--execute-from",
+                "echo 'Hello'",
+                "--execute-until",
+                "$ echo 'Hello'",
+            ],
+        );
+        assert_eq!("---\n$ echo 'Hello'\nHello\n", output);
+    }
+
+    #[test]
+    fn run_with_verbose_execute_from_args() {
+        let dir = "./target/fixtures/run_with_verbose_execute_from_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Hello'\n```\n\n```shell\n$ echo 'Hello'\n```\n",
+        );
+        let markdown = fs::canonicalize(format!("{}/README.md", dir))
+            .expect("Failed to canonicalize the fixture");
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--verbose", "--execute-from", "echo 'Hello'"])
+            .output()
+            .expect("Failed to run the command");
+        assert!(output.status.success());
+        assert_eq!(
+            format!(
+                "Executing from line 2 of {}: echo 'Hello'\n",
+                markdown.display()
+            ),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn run_with_trailing_and_or_commands() {
+        let dir = "./target/fixtures/run_with_trailing_and_or_commands";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+$ echo a &&
+echo b
+$ false ||
+echo c
+```
+"#,
+        );
+
+        let output = run_with_args(dir, &[]);
+        assert_eq!(
+            "---\n$ echo a &&\n> echo b\n> false ||\n> echo c\na\nb\nc\n",
+            output
+        );
+    }
+
+    #[test]
+    fn run_with_prepend_and_append_args() {
+        let dir = "./target/fixtures/run_with_prepend_and_append_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+"#,
+        );
+
+        let output = run_with_args(
+            dir,
+            &[
+                "--no-separator",
+                "--prepend",
+                "echo 'Setup'",
+                "--append",
+                "echo 'Teardown'",
+            ],
+        );
+        assert_eq!(
+            "$ echo 'Setup'\nSetup\n$ echo 'Hello'\nHello\n$ echo 'Teardown'\nTeardown\n",
+            output
+        );
+    }
+
+    #[test]
+    fn run_with_config_file() {
+        let dir = "./target/fixtures/run_with_config_file";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/DOCS.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+
+```shell
+echo 'Skipped'
+```
+"#,
+        );
+        new_fixture(
+            &format!("{}/.me.toml", dir),
+            r#"file_name = "DOCS.md"
+skip_commands = "Skipped"
+separator = "==="
+"#,
+        );
+        new_fixture(&format!("{}/other.toml", dir), "separator = \"***\"\n");
+
+        let output = run_with_args(dir, &[]);
+        assert_eq!("===\n$ echo 'Hello'\nHello\n", output);
+
+        let output = run_with_args(dir, &["--separator", "---"]);
+        assert_eq!("---\n$ echo 'Hello'\nHello\n", output);
+
+        let output = run_with_args(dir, &["--config", "other.toml", "--file-name", "DOCS.md"]);
+        assert_eq!(
+            "***\n$ echo 'Hello'\nHello\n***\n$ echo 'Skipped'\nSkipped\n",
+            output
+        );
+
+        new_fixture(&format!("{}/invalid.toml", dir), "unknown = true\n");
+        me().current_dir(dir)
+            .args(["--config", "invalid.toml"])
+            .assert()
+            .stdout("")
+            .failure();
+    }
+
+    #[test]
+    fn run_with_config_file_execution_mode_and_shell() {
+        let dir = "./target/fixtures/run_with_config_file_execution_mode_and_shell";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+        new_fixture(
+            &format!("{}/.me.toml", dir),
+            "delay = \"1s\"\nshell = \"/bin/bash\"\n",
+        );
+
+        let output = run_with_args(dir, &["--explain"]);
+        assert!(output.contains("\nexecution mode: wait 1000ms between commands\n"));
+        assert!(output.contains("\nshebang: #!/bin/bash\n"));
+
+        let output = run_with_args(dir, &["--explain", "--parallel", "2", "--shell", "/bin/sh"]);
+        assert!(output.contains("\nexecution mode: parallel, up to 2 jobs\n"));
+        assert!(output.contains("\nshebang: #!/bin/sh\n"));
+
+        new_fixture(&format!("{}/.me.toml", dir), "parallel = 2\n");
+        let output = run_with_args(dir, &["--explain"]);
+        assert!(output.contains("\nexecution mode: parallel, up to 2 jobs\n"));
+
+        let output = run_with_args(dir, &["--format", "tap"]);
+        assert_eq!("ok 1 - echo 'Hello'\n1..1\n", output);
+
+        for config in [
+            "retry = 2\ndelay = \"1s\"\n",
+            "delay = \"1h\"\n",
+            "parallel = 0\n",
+        ] {
+            new_fixture(&format!("{}/.me.toml", dir), config);
+            me().current_dir(dir).assert().stdout("").failure();
+        }
+    }
+
+    #[test]
+    fn run_with_file_name_env() {
+        let dir = "./target/fixtures/run_with_file_name_env";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'README'\n```\n",
+        );
+        new_fixture(&format!("{}/DOCS.md", dir), "```shell\necho 'DOCS'\n```\n");
+        new_fixture(&format!("{}/.me.toml", dir), "file_name = \"README.md\"\n");
+
+        let run = |args: &[&str], expected: &str| {
+            me().current_dir(dir)
+                .args(args)
+                .env("ME_FILE", "DOCS.md")
+                .assert()
+                .stdout(expected.to_string())
+                .success();
+        };
+        run(&["--no-separator"], "$ echo 'DOCS'\nDOCS\n");
+        run(
+            &["--no-separator", "--file-name", "README.md"],
+            "$ echo 'README'\nREADME\n",
+        );
+
+        let output = run_with_args(dir, &["--no-separator"]);
+        assert_eq!("$ echo 'README'\nREADME\n", output);
+    }
+
+    #[test]
+    fn run_with_explain_args() {
+        let dir = "./target/fixtures/run_with_explain_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/DOCS.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Not run'
+```
+"#,
+        );
+        new_fixture(
+            &format!("{}/.me.toml", dir),
+            r#"file_name = "DOCS.md"
+skip_commands = "Skipped"
+tag = "build"
+retry = 3
+"#,
+        );
+
+        let output = run_with_args(
+            dir,
+            &[
+                "--explain",
+                "--tag",
+                "test",
+                "--execute-from-prefix",
+                "echo",
+            ],
+        );
+        let (files, output) = output.split_once('\n').expect("Missing the files line");
+        assert!(files.starts_with("files: ") && files.ends_with("DOCS.md"));
+        assert_eq!(
+            r#"recursive: none
+execution mode: retry up to 3 attempts, starting with a 1000ms delay
+shell: none
+shebang: #!/bin/sh
+languages: shell
+skip commands: Skipped
+tag: test
+section: none
+execute from: echo (prefix)
+execute until: none
+skip first: 0
+"#,
+            output
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_interrupted() {
+        let dir = "./target/fixtures/run_interrupted";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\ntouch started\nsleep 2\n```\n\n```shell\ntouch not-run\n```\n",
+        );
+
+        /* Interrupts are ignored by processes started in the background, unlike from a terminal */
+        let mut command = process::Command::new(assert_cmd::cargo::cargo_bin("../release/me"));
+        unsafe {
+            command.pre_exec(|| {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+                Ok(())
+            })
+        };
+        let mut child = command
+            .current_dir(dir)
+            .env_remove("SHELL")
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .expect("Failed to run test command");
+
+        let started = Path::new(dir).join("started");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !started.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+
+        let status = child.wait().expect("Failed to wait for the test command");
+        assert_eq!(Some(130), status.code());
+        assert!(!Path::new(dir).join("not-run").exists());
+        let scripts = fs::read_dir(dir)
+            .expect("Failed to list the fixtures")
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(".me-commands-")
+            })
+            .count();
+        assert_eq!(0, scripts);
+    }
+
+    #[test]
+    fn run_with_config_file_per_file_skip_commands() {
+        let dir = "./target/fixtures/run_with_config_file_per_file_skip_commands";
+        remove_fixtures(dir);
+        let content = "```shell\necho 'First'\n```\n\n```shell\necho 'Second'\n```\n";
+        new_fixture(&format!("{}/README.md", dir), content);
+        new_fixture(&format!("{}/a/README.md", dir), content);
+        new_fixture(&format!("{}/b/README.md", dir), content);
+        new_fixture(
+            &format!("{}/.me.toml", dir),
+            r#"recursive = 2
+skip_commands = "First"
+separator = "==="
+
+[[files]]
+path = "a/*.md"
+skip_commands = "Second"
+
+[[files]]
+path = "b/*.md"
+"#,
+        );
+
+        let output = run_with_args(dir, &[]);
+        assert_eq!(
+            r#"===
+$ echo 'Second'
+Second
+===
+$ echo 'First'
+First
+===
+$ echo 'First'
+First
+===
+$ echo 'Second'
+Second
+"#,
+            output
+        );
+
+        let output = run_with_args(dir, &["--skip-commands", "Second"]);
+        assert_eq!("===\n$ echo 'First'\nFirst\n".repeat(3), output);
+    }
+
+    #[test]
+    fn run_with_recursive_args() {
+        let dir = "./target/fixtures/run_with_recursive_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 1'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 2'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/a/b/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 3'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--recursive"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Level 1'
+Level 1
+---
+$ echo 'Level 2'
+Level 2
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_unreadable_file_and_no_fail_fast_args() {
+        let dir = "./target/fixtures/run_with_unreadable_file_and_no_fail_fast_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+        fs::write(format!("{}/README.md", dir), [0xff, 0xfe, b'\n'])
+            .expect("Failed to write the fixture");
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive", "--no-fail-fast", "--no-separator"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!(
+            "$ echo 'Hello'\nHello\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .starts_with("Failed to read the MARKDOWN file "));
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!("", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn run_with_allow_missing_file_args() {
+        let dir = "./target/fixtures/run_with_allow_missing_file_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--file-name", "MISSING.md", "--allow-missing-file"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .starts_with("Warning: skipping the MARKDOWN file "));
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--file-name", "MISSING.md"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .starts_with("Failed to read the MARKDOWN file "));
+    }
+
+    #[test]
+    fn run_with_print_files_args() {
+        let dir = "./target/fixtures/run_with_print_files_args";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/README.md", dir), "```shell\nexit 1\n```\n");
+        new_fixture(&format!("{}/a/README.md", dir), "```shell\nexit 1\n```\n");
+        new_fixture(&format!("{}/a/b/README.md", dir), "```shell\nexit 1\n```\n");
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive", "--print-files"])
+            .output()
+            .expect("Failed to run the command");
+        assert!(output.status.success());
+
+        let dir = fs::canonicalize(dir).expect("Failed to canonicalize the fixtures directory");
+        let expected = format!(
+            "{}\n{}\n",
+            dir.join("README.md").display(),
+            dir.join("a/README.md").display()
+        );
+        assert_eq!(expected, String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    fn run_with_count_args() {
+        let dir = "./target/fixtures/run_with_count_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\nexit 1\n```\n\n```shell\nexit 2\n```\n",
+        );
+        new_fixture(&format!("{}/a/README.md", dir), "# No commands\n");
+        new_fixture(&format!("{}/b/README.md", dir), "```shell\nexit 3\n```\n");
+
+        let output = run_with_args(dir, &["--recursive", "--count", "--prepend", "exit 4"]);
+
+        let dir = fs::canonicalize(dir).expect("Failed to canonicalize the fixtures directory");
+        let expected = format!(
+            "{}: 2\n{}: 0\n{}: 1\ntotal: 3\n",
+            dir.join("README.md").display(),
+            dir.join("a/README.md").display(),
+            dir.join("b/README.md").display()
+        );
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn run_with_check_args() {
+        let dir = "./target/fixtures/run_with_check_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\ntouch created.txt\n```\n",
+        );
+        new_fixture(
+            &format!("{}/INVALID.md", dir),
+            "```shell\ntouch created.txt\necho 'Unbalanced\n```\n",
+        );
+
+        let output = run_with_args(dir, &["--check"]);
+        assert_eq!("", output);
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--check", "--file-name", "INVALID.md"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(!output.status.success());
+        assert_eq!("", String::from_utf8_lossy(&output.stdout));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("have syntax errors"));
+        assert!(!Path::new(dir).join("created.txt").exists());
+
+        new_fixture(
+            &format!("{}/BASH.md", dir),
+            "```shell\nitems=(a b)\necho \"${items[1]}\"\n```\n",
+        );
+        let output = run_with_args(
+            dir,
+            &[
+                "--check",
+                "--file-name",
+                "BASH.md",
+                "--shebang",
+                "#!/usr/bin/env bash",
+            ],
+        );
+        assert_eq!("", output);
+    }
+
+    #[test]
+    fn run_with_error_format_json_args() {
+        let dir = "./target/fixtures/run_with_error_format_json_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "# README Fixture\n\n```shell\necho 'Unclosed'\n",
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--error-format", "json"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+
+        let path = fs::canonicalize(format!("{}/README.md", dir))
+            .expect("Failed to canonicalize the fixture");
+        let expected = format!(
+            "{{\"file\":\"{}\",\"kind\":\"parse\",\"message\":\"Failed to find closing code block for the command on line 4\",\"line\":4}}\n",
+            path.display()
+        );
+        assert_eq!(expected, String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn run_with_error_format_json_and_missing_working_dir() {
+        let dir = "./target/fixtures/run_with_error_format_json_and_missing_working_dir";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "# @workdir ../missing\n\n```shell\necho 'Not executed'\n```\n",
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--error-format", "json"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!("", String::from_utf8_lossy(&output.stdout));
+
+        let path = fs::canonicalize(format!("{}/README.md", dir))
+            .expect("Failed to canonicalize the fixture");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let prefix = format!(
+            "{{\"file\":\"{}\",\"kind\":\"workdir\",\"message\":\"",
+            path.display()
+        );
+        assert!(stderr.starts_with(&prefix), "{}", stderr);
+        assert!(stderr.ends_with("\"line\":null}\n"), "{}", stderr);
+        assert_eq!(1, stderr.lines().count());
+    }
+
+    #[test]
+    fn run_with_error_format_json_and_glob_without_matches() {
+        let dir = "./target/fixtures/run_with_error_format_json_and_glob_without_matches";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Not matched'\n```\n",
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--error-format", "json", "--glob", "docs/*.md"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(2), output.status.code());
+        assert_eq!(
+            "{\"file\":null,\"kind\":\"no-matches\",\"message\":\"No MARKDOWN files match the glob pattern: docs/*.md\",\"line\":null}\n",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn run_with_error_format_json_and_invalid_options() {
+        let dir = "./target/fixtures/run_with_error_format_json_and_invalid_options";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            "```shell\necho 'Hello'\n```\n",
+        );
+        new_fixture(&format!("{}/invalid.toml", dir), "unknown = true\n");
+
+        let run = |args: &[&str]| {
+            let output = me()
+                .current_dir(dir)
+                .args(["--error-format", "json"])
+                .args(args)
+                .output()
+                .expect("Failed to run test command");
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            assert_eq!(1, stderr.lines().count(), "{}", stderr);
+            (output.status.code(), stderr)
+        };
+
+        let (code, stderr) = run(&["--config", "invalid.toml"]);
+        assert_eq!(Some(2), code);
+        assert!(stderr.starts_with("{\"file\":\"invalid.toml\",\"kind\":\"config\","));
+
+        let (code, stderr) = run(&["--since", "no-such-reference"]);
+        assert_eq!(Some(2), code);
+        assert!(stderr.starts_with("{\"file\":null,\"kind\":\"since\","));
+
+        let (code, stderr) = run(&["--recursive", "--output", "script.sh"]);
+        assert_eq!(Some(2), code);
+        assert_eq!(
+            "{\"file\":null,\"kind\":\"output\",\"message\":\"The --output option cannot be used when more than one MARKDOWN file is found\",\"line\":null}\n",
+            stderr
+        );
+
+        let (code, stderr) = run(&["--file-name", "MISSING.md", "--allow-missing-file"]);
+        assert_eq!(Some(0), code);
+        assert!(stderr.contains("\"kind\":\"skipped\""), "{}", stderr);
+        assert!(stderr.contains("MISSING.md\""), "{}", stderr);
+
+        let (code, stderr) = run(&["--skip-commands", "docker", "--no-separator"]);
+        assert_eq!(Some(0), code);
+        assert!(stderr.starts_with("{\"file\":null,\"kind\":\"no-skipped-commands\","));
+    }
+
+    #[test]
+    fn run_with_recursive_and_first_only_args() {
+        let dir = "./target/fixtures/run_with_recursive_and_first_only_args";
+        remove_fixtures(dir);
+        let content = "```shell\necho 'First'\n```\n\n```shell\necho 'Second'\n```\n";
+        new_fixture(&format!("{}/README.md", dir), content);
+        new_fixture(&format!("{}/a/README.md", dir), content);
+
+        let output = run_with_args(dir, &["--recursive", "--first-only", "--no-separator"]);
+        assert_eq!("$ echo 'First'\nFirst\n".repeat(2), output);
+    }
+
+    #[test]
+    fn run_with_shuffle_args() {
+        let dir = "./target/fixtures/run_with_shuffle_args";
+        remove_fixtures(dir);
+        let content = (1..=8)
+            .map(|index| format!("```shell\necho '{}'\n```\n", index))
+            .collect::<Vec<_>>()
+            .join("\n");
+        new_fixture(&format!("{}/README.md", dir), &content);
+
+        let run = |seed: &str| run_with_args(dir, &["--shuffle", seed, "--no-separator"]);
+        let output = run("42");
+        assert_eq!(output, run("42"));
+
+        let mut lines: Vec<_> = output.lines().collect();
+        lines.sort();
+        let expected: Vec<_> = (1..=8)
+            .flat_map(|index| [format!("$ echo '{}'", index), index.to_string()])
+            .collect();
+        let mut expected: Vec<_> = expected.iter().map(String::as_str).collect();
+        expected.sort();
+        assert_eq!(expected, lines);
+    }
+
+    #[test]
+    fn run_with_since_args() {
+        let dir = "./target/fixtures/run_with_since_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Root'\n```\n",
+        );
+        new_fixture(&format!("{}/a/README.md", dir), "```shell\necho 'A'\n```\n");
+
+        let git = |args: &[&str]| {
+            let status = process::Command::new("git")
+                .current_dir(dir)
+                .args(["-c", "user.name=me", "-c", "user.email=me@example.com"])
+                .args(args)
+                .output()
+                .expect("Failed to run git")
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "--message", "Initial commit"]);
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            "```shell\necho 'Changed'\n```\n",
+        );
+
+        me().current_dir(dir)
+            .args(["--recursive", "--since", "HEAD", "--no-separator"])
+            .assert()
+            .stdout("$ echo 'Changed'\nChanged\n")
+            .success();
+
+        me().current_dir(dir)
+            .args(["--since", "unknown-reference"])
+            .assert()
+            .failure();
+    }
+
+    #[test]
+    fn run_with_progress_args() {
+        let dir = "./target/fixtures/run_with_progress_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Root'\n```\n",
+        );
+        new_fixture(&format!("{}/a/README.md", dir), "```shell\necho 'A'\n```\n");
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive", "--progress", "--no-separator"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(output.status.success());
+        assert_eq!(
+            "$ echo 'Root'\nRoot\n$ echo 'A'\nA\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<_> = stderr.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("[1/2] Running ") && lines[0].ends_with("README.md"));
+        assert!(lines[1].starts_with("[2/2] Running ") && lines[1].ends_with("a/README.md"));
+    }
+
+    #[test]
+    fn run_with_unlimited_max_depth_args() {
+        let dir = "./target/fixtures/run_with_unlimited_max_depth_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 1'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/a/b/c/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 4'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--max-depth", "0"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Level 1'
+Level 1
+---
+$ echo 'Level 4'
+Level 4
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_recursive_and_exclude_args() {
+        let dir = "./target/fixtures/run_with_recursive_and_exclude_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 1'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/node_modules/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Excluded'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/node_modules/a/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Excluded subdirectory'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/src/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 2'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--recursive", "3", "--exclude", "node_*"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Level 1'
+Level 1
+---
+$ echo 'Level 2'
+Level 2
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_recursive_and_fail_fast_args() {
+        let dir = "./target/fixtures/run_with_recursive_and_fail_fast_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 1'
+false
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 2'
+```
+"#,
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!(
+            "---\n$ echo 'Level 1'\n> false\nLevel 1\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("The commands failed in the following files:\n"));
+        assert!(stderr.ends_with("/run_with_recursive_and_fail_fast_args/README.md\n"));
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive", "--no-fail-fast"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!(
+            "---\n$ echo 'Level 1'\n> false\nLevel 1\n---\n$ echo 'Level 2'\nLevel 2\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.ends_with("/run_with_recursive_and_fail_fast_args/README.md\n"));
+    }
+
+    #[test]
+    fn run_with_recursive_and_gitignore() {
+        let dir = "./target/fixtures/run_with_recursive_and_gitignore";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/.gitignore", dir), "target/\n");
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Level 1'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/target/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Ignored'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--recursive"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Level 1'
+Level 1
+"#
+                .to_string(),
+            )
+            .success();
+
+        me().current_dir(dir)
+            .args(["--recursive", "--no-ignore"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Level 1'
+Level 1
+---
+$ echo 'Ignored'
+Ignored
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_recursive_and_meignore() {
+        let dir = "./target/fixtures/run_with_recursive_and_meignore";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/.meignore", dir), "drafts/\n");
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'Level 1'\n```\n",
+        );
+        new_fixture(
+            &format!("{}/drafts/README.md", dir),
+            "```shell\necho 'Draft'\n```\n",
+        );
+        new_fixture(&format!("{}/docs/.meignore", dir), "README.md\n");
+        new_fixture(
+            &format!("{}/docs/README.md", dir),
+            "```shell\necho 'Ignored'\n```\n",
+        );
+        new_fixture(
+            &format!("{}/docs/guide/README.md", dir),
+            "```shell\necho 'Also ignored'\n```\n",
+        );
+
+        let output = run_with_args(dir, &["--recursive", "3", "--no-ignore"]);
+        assert_eq!("---\n$ echo 'Level 1'\nLevel 1\n", output);
+    }
+
+    #[test]
+    fn run_with_stdin_args() {
+        let dir = "./target/fixtures/run_with_stdin_args";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/.keep", dir), "");
+
+        for args in [&["--stdin"][..], &["-"], &["--file-name", "-"]] {
+            me().current_dir(dir)
+                .args(args)
+                .write_stdin(
+                    r#"# README Fixture
+```shell
+basename "$(pwd)"
+```
+"#,
+                )
+                .assert()
+                .stdout(
+                    r#"---
+$ basename "$(pwd)"
+run_with_stdin_args
+"#
+                    .to_string(),
+                )
+                .success();
+        }
+    }
+
+    #[test]
+    fn run_with_stdin_and_recursive_args() {
+        let dir = "./target/fixtures/run_with_stdin_and_recursive_args";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/.keep", dir), "");
+
+        for args in [
+            &["--stdin", "--recursive"][..],
+            &["-", "--recursive"],
+            &["--file-name", "-", "--recursive"],
+        ] {
+            me().current_dir(dir)
+                .args(args)
+                .write_stdin("# README Fixture\n")
+                .assert()
+                .stdout("")
+                .failure();
+        }
+
+        new_fixture(&format!("{}/.me.toml", dir), "recursive = 3\n");
+        for args in [&["--stdin"][..], &["-"], &["--file-name", "-"]] {
+            me().current_dir(dir)
+                .args(args)
+                .arg("--no-separator")
+                .write_stdin("```shell\necho 'Hello'\n```\n")
+                .assert()
+                .stdout("$ echo 'Hello'\nHello\n")
+                .success();
+        }
+    }
+
+    #[test]
+    fn run_with_glob_args() {
+        let dir = "./target/fixtures/run_with_glob_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Not matched'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/docs/b.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Document B'
+```
+"#,
+        );
+
+        new_fixture(
+            &format!("{}/docs/a/c.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Document C'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--recursive", "--glob", "docs/**/*.md"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Document C'
+Document C
+---
+$ echo 'Document B'
+Document B
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_glob_args_without_matches() {
+        let dir = "./target/fixtures/run_with_glob_args_without_matches";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Not matched'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--glob", "docs/**/*.md"])
+            .assert()
+            .stdout("")
+            .failure();
+    }
+
+    #[test]
+    fn run_with_missing_working_dir_directive() {
+        let dir = "./target/fixtures/run_with_missing_working_dir_directive";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "# @workdir ../missing\n\n```shell\necho 'Not executed'\n```\n",
+        );
+        new_fixture(
+            &format!("{}/a/README.md", dir),
+            "```shell\necho 'Still runs'\n```\n",
+        );
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--recursive", "--no-fail-fast", "--no-separator"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert_eq!(
+            "$ echo 'Still runs'\nStill runs\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("Failed to resolve the working directory of "));
+        assert!(!stderr.contains("panicked"));
+    }
+
+    #[test]
+    fn run_with_working_dir_directive() {
+        let dir = "./target/fixtures/run_with_working_dir_directive";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/docs/README.md", dir),
+            r#"# @workdir ../build
+
+# README Fixture
+```shell
+basename "$(pwd)"
+```
+"#,
+        );
+
+        new_fixture(&format!("{}/build/.keep", dir), "");
+
+        me().current_dir(dir)
+            .args(["--file-name", "docs/README.md"])
+            .assert()
+            .stdout(
+                r#"---
+$ basename "$(pwd)"
+build
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_repeat_args() {
+        let dir = "./target/fixtures/run_with_repeat_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--repeat", "2"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Hello'
+Hello
+---
+$ echo 'Hello'
+Hello
+"#
+                .to_string(),
+            )
+            .success();
+
+        new_fixture(&format!("{}/README.md", dir), "```shell\nsleep 0.1\n```\n");
+        let output = me()
+            .current_dir(dir)
+            .args(["--repeat", "2"])
+            .output()
+            .expect("Failed to run test command");
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let timing = Regex::new(
+            r"^Iteration 1 took \d+\.\d{3} seconds\nIteration 2 took \d+\.\d{3} seconds\n$",
+        )
+        .expect("Invalid regex");
+        assert!(timing.is_match(&stderr), "{}", stderr);
+        assert!(!stderr.contains("took 0.000 seconds"), "{}", stderr);
+    }
+
+    #[test]
+    fn run_with_retry_args() {
+        let dir = "./target/fixtures/run_with_retry_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+test -f 'attempted.txt' || { touch 'attempted.txt'; false; }
+```
+
+```shell
+echo 'Done'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--retry", "2", "--retry-delay", "10"])
+            .assert()
+            .stdout(
+                r#"---
+$ test -f 'attempted.txt' || { touch 'attempted.txt'; false; }
+---
+$ echo 'Done'
+Done
+"#
+                .to_string(),
+            )
+            .success();
+
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\nfalse\necho 'After'\n```\n",
+        );
+        me().current_dir(dir)
+            .args(["--retry", "2", "--retry-delay", "10", "--no-separator"])
+            .assert()
+            .stdout("$ false\n> echo 'After'\n")
+            .failure();
+    }
+
+    #[test]
+    fn run_with_timeout_args() {
+        let dir = "./target/fixtures/run_with_timeout_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Quick'
+```
+
+```shell
+sleep 5
+```
+
+```shell
+echo 'Not executed'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--timeout", "1"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Quick'
+Quick
+---
+$ sleep 5
+"#
+                .to_string(),
+            )
+            .failure();
+    }
+
+    #[test]
+    fn run_with_shell_env_and_backslashes() {
+        let dir = "./target/fixtures/run_with_shell_env_and_backslashes";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"```shell
+printf '%s %s\n' 'a\\b' \
+  'c'
+```
+"#,
+        );
+
+        for shell in ["/bin/bash", "/bin/sh", "/usr/bin/fish", "/bin/csh"] {
+            let output = me()
+                .current_dir(dir)
+                .args(["--no-separator"])
+                .env("SHELL", shell)
+                .output()
+                .expect("Failed to run test command");
+            assert!(output.status.success());
+            assert_eq!(
+                r#"$ printf '%s %s\n' 'a\\b' \
+>   'c'
+a\\b c
+"#,
+                String::from_utf8_lossy(&output.stdout)
+            );
+        }
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--explain"])
+            .env("SHELL", "/usr/bin/fish")
+            .output()
+            .expect("Failed to run test command");
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\nshebang: #!/bin/sh\n"));
+    }
+
+    #[test]
+    fn run_with_timeout_and_shell_args() {
+        let dir = "./target/fixtures/run_with_timeout_and_shell_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\n[[ 'a' == 'a' ]] && echo 'Bash'\n```\n",
+        );
+
+        let output = run_with_args(
+            dir,
+            &["--shell", "/bin/bash", "--timeout", "5", "--no-separator"],
+        );
+        assert_eq!("$ [[ 'a' == 'a' ]] && echo 'Bash'\nBash\n", output);
+    }
+
+    #[test]
+    fn run_with_no_preamble_args() {
+        let dir = "./target/fixtures/run_with_no_preamble_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\nfalse\n```\n\n```shell\necho 'Still runs'\n```\n",
+        );
+
+        let output = run_with_args(dir, &["--no-preamble", "--no-separator"]);
+        assert_eq!("$ false\n$ echo 'Still runs'\nStill runs\n", output);
+
+        let output = run_with_args(dir, &["--no-preamble", "--no-exec"]);
+        assert!(output.starts_with("printf '%s\\n' '---'\nprintf '%s\\n' '$ false'\nfalse\n"));
+    }
+
+    #[test]
+    fn run_with_expected_status() {
+        let dir = "./target/fixtures/run_with_expected_status";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell expect_fail
+echo 'Failing'
+false
+echo 'Not executed'
+```
+
+```shell
+# expect: 3
+sh -c 'exit 3'
+```
+
+```shell
+echo 'Continued'
+```
+"#,
+        );
+
+        let output = run_with_args(dir, &["--no-separator"]);
+        assert_eq!(
+            "$ echo 'Failing'\n> false\n> echo 'Not executed'\nFailing\n$ sh -c 'exit 3'\n$ echo 'Continued'\nContinued\n",
+            output
+        );
+
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell {expect=3}\necho 'Passing'\n```\n\n```shell\necho 'Not executed'\n```\n",
+        );
+        let output = me()
+            .current_dir(dir)
+            .args(["--no-separator"])
+            .output()
+            .expect("Failed to run the command");
+        assert!(!output.status.success());
+        assert_eq!(
+            "$ echo 'Passing'\nPassing\n",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .contains("Expected the command to exit with 3, but it exited with 0"));
+    }
+
+    #[test]
+    fn run_with_quiet_success_args() {
+        let dir = "./target/fixtures/run_with_quiet_success_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Passing'
+```
+
+```shell
+echo 'Failing'
+false
+```
+
+```shell
+echo 'Not executed'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--quiet-success"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Failing'
+> false
+Failing
+"#
+                .to_string(),
+            )
+            .failure();
+    }
+
+    #[test]
+    fn run_with_working_dir_args() {
+        let dir = "./target/fixtures/run_with_working_dir_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/docs/README.md", dir),
+            r#"# @workdir ../build
+
+# README Fixture
+```shell
+basename "$(pwd)"
+```
+"#,
+        );
+
+        new_fixture(&format!("{}/build/.keep", dir), "");
+
+        me().current_dir(dir)
+            .args(["--file-name", "docs/README.md", "--working-dir", "."])
+            .assert()
+            .stdout(
+                r#"---
+$ basename "$(pwd)"
+run_with_working_dir_args
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_me_dir_env() {
+        let dir = "./target/fixtures/run_with_me_dir_env";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/docs/README.md", dir),
+            "```shell\necho \"$ME_DIR\"\n```\n",
+        );
+
+        let output = run_with_args(
+            dir,
+            &[
+                "--file-name",
+                "docs/README.md",
+                "--working-dir",
+                ".",
+                "--no-separator",
+            ],
+        );
+
+        let docs = fs::canonicalize(format!("{}/docs", dir))
+            .expect("Failed to canonicalize the fixtures directory");
+        assert_eq!(format!("$ echo \"$ME_DIR\"\n{}\n", docs.display()), output);
+    }
+
+    #[test]
+    fn run_with_env_args() {
+        let dir = "./target/fixtures/run_with_env_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo "${API_URL} ${API_KEY}"
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .env("API_URL", "production")
+            .args(["--env", "API_URL=staging", "--env", "API_KEY=a=b"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo "${API_URL} ${API_KEY}"
+staging a=b
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_invalid_env_args() {
+        let dir = "./target/fixtures/run_with_invalid_env_args";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/README.md", dir), "# README Fixture\n");
+
+        me().current_dir(dir)
+            .args(["--env", "API_URL"])
+            .assert()
+            .stdout("")
+            .failure();
+    }
+
+    #[test]
+    fn run_with_no_exec_args() {
+        let dir = "./target/fixtures/run_with_no_exec_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello' > created.txt
+```
+"#,
+        );
+
+        let no_exec = run_with_args(dir, &["--no-exec", "--format", "script"]);
+        let dry_run = run_with_args(dir, &["--dry-run"]);
+        assert_eq!(dry_run, no_exec);
+        assert!(dry_run.starts_with("#!/bin/sh\n"));
+
+        let no_exec = run_with_args(dir, &["--no-exec", "--format", "list"]);
+        let list = run_with_args(dir, &["--list"]);
+        assert_eq!(list, no_exec);
+        assert_eq!("echo 'Hello' > created.txt\n", list);
+
+        let json = run_with_args(dir, &["--no-exec", "--format", "json"]);
+        assert_eq!(
+            "[{\"command\":\"echo 'Hello' > created.txt\",\"lines\":[\"echo 'Hello' > created.txt\"],\"tag\":null,\"line_number\":3}]\n",
+            json
+        );
+
+        for args in [
+            ["--dry-run", "--format", "json"],
+            ["--dry-run", "--format", "script"],
+            ["--list", "--format", "json"],
+            ["--list", "--format", "list"],
+        ] {
+            me().current_dir(dir).args(args).assert().stdout("").code(2);
+        }
+
+        assert!(!Path::new(&format!("{}/created.txt", dir)).exists());
+    }
+
+    #[test]
+    fn run_with_from_list_args() {
+        let dir = "./target/fixtures/run_with_from_list_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+
+```shell
+echo 'Multi' \
+  'line'
+```
+"#,
+        );
+
+        let list = run_with_args(dir, &["--list"]);
+        assert_eq!("echo 'Hello'\n\necho 'Multi' \\\n\t  'line'\n", list);
+
+        new_fixture(&format!("{}/commands.txt", dir), &list);
+        me().current_dir(dir)
+            .args(["--from-list", "commands.txt"])
+            .assert()
+            .stdout(
+                r#"---
+$ echo 'Hello'
+Hello
+---
+$ echo 'Multi' \
+>   'line'
+Multi line
+"#
+                .to_string(),
+            )
+            .success();
+    }
+
+    #[test]
+    fn run_with_block_title() {
+        let dir = "./target/fixtures/run_with_block_title";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell title=\"C:\\\\new\"\necho 'Hello'\n```\n",
+        );
+
+        let output = run_with_args(dir, &[]);
+        assert_eq!("---\n=== C:\\\\new ===\n$ echo 'Hello'\nHello\n", output);
+    }
+
+    #[test]
+    fn run_with_echo_comments_args() {
+        let dir = "./target/fixtures/run_with_echo_comments_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\n# Copy to C:\\new\necho 'Hello'\n```\n",
+        );
+
+        let output = run_with_args(dir, &["--echo-comments", "--no-separator"]);
+        assert_eq!(
+            "$ # Copy to C:\\new\n> echo 'Hello'\nCopy to C:\\new\nHello\n",
+            output
+        );
+    }
+
+    #[test]
+    fn run_with_separator_args() {
+        let dir = "./target/fixtures/run_with_separator_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+"#,
+        );
+
+        let output = run_with_args(dir, &["--separator", "=== Next ==="]);
+        assert_eq!("=== Next ===\n$ echo 'Hello'\nHello\n", output);
+
+        let output = run_with_args(dir, &["--separator", "--\\c"]);
+        assert_eq!("--\\c\n$ echo 'Hello'\nHello\n", output);
+
+        let output = run_with_args(dir, &["--no-separator"]);
+        assert_eq!("$ echo 'Hello'\nHello\n", output);
+    }
+
+    #[test]
+    fn run_with_prefix_output_args() {
+        let dir = "./target/fixtures/run_with_prefix_output_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+
+```shell
+echo 'World'
+```
+"#,
+        );
+
+        let output = run_with_args(dir, &["--prefix-output"]);
+        assert_eq!(
+            "[1] ---\n[1] $ echo 'Hello'\n[1] Hello\n[2] ---\n[2] $ echo 'World'\n[2] World\n",
+            output
+        );
+
+        new_fixture(
+            &format!("{}/README.md", dir),
+            "```shell\necho 'First' >&2\n```\n\n```shell\necho 'Second' >&2\n```\n\n```shell\necho 'Third' >&2\n```\n",
+        );
+        for _ in 0..5 {
+            let output = me()
+                .current_dir(dir)
+                .args(["--prefix-output", "--no-separator"])
+                .output()
+                .expect("Failed to run test command");
+            assert!(output.status.success());
+            assert_eq!(
+                "[1] First\n[2] Second\n[3] Third\n",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    #[test]
+    fn run_with_output_args() {
+        let dir = "./target/fixtures/run_with_output_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello' > created.txt
+```
+"#,
+        );
+
+        let dry_run = run_with_args(dir, &["--dry-run"]);
+
+        let output = run_with_args(dir, &["--dry-run", "--output", "script.sh"]);
+        assert_eq!("", output);
+        assert!(!Path::new(&format!("{}/created.txt", dir)).exists());
+        let script = fs::read_to_string(format!("{}/script.sh", dir))
+            .expect("Failed to read the shell script");
+        assert_eq!(dry_run, script);
+
+        run_with_args(dir, &["--output", "script.sh"]);
+        assert!(Path::new(&format!("{}/created.txt", dir)).exists());
+
+        let output = me()
+            .current_dir(dir)
+            .args(["--dry-run", "--output", "missing/script.sh"])
+            .output()
+            .expect("Failed to run test command");
+        assert_eq!(Some(1), output.status.code());
+        assert!(String::from_utf8_lossy(&output.stderr)
+            .starts_with("Failed to create the shell script for "));
+        assert!(Path::new(&format!("{}/script.sh", dir)).exists());
+    }
+
+    #[test]
+    fn run_with_recursive_and_output_args() {
+        let dir = "./target/fixtures/run_with_recursive_and_output_args";
+        remove_fixtures(dir);
+        new_fixture(&format!("{}/README.md", dir), "# README Fixture\n");
+        new_fixture(&format!("{}/a/README.md", dir), "# README Fixture\n");
+
+        me().current_dir(dir)
+            .args(["--recursive", "--output", "script.sh"])
+            .assert()
+            .stdout("")
+            .failure();
+    }
+
+    #[test]
+    fn run_with_multiple_no_exec_args() {
+        let dir = "./target/fixtures/run_with_multiple_no_exec_args";
+        remove_fixtures(dir);
+        new_fixture(
+            &format!("{}/README.md", dir),
+            r#"# README Fixture
+```shell
+echo 'Hello'
+```
+"#,
+        );
+
+        me().current_dir(dir)
+            .args(["--dry-run", "--list"])
+            .assert()
+            .stdout("")
+            .failure();
+    }
+
+    /// Returns the command under test, ignoring the `SHELL` environment variable of the caller.
+    fn me() -> Command {
+        let mut command =
+            Command::cargo_bin("../release/me").expect("Failed to create test command");
+        command.env_remove("SHELL");
+        command
+    }
+
+    fn run_with_args(dir: &str, args: &[&str]) -> String {
+        let output = me()
+            .current_dir(dir)
+            .args(args)
+            .env_remove("ME_FILE")
+            .output()
+            .expect("Failed to run test command");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).expect("Failed to read the command output")
     }
 
     fn new_fixture(fixture_path: &str, content: &str) {
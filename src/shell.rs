@@ -1,36 +1,361 @@
+use std::env;
+use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+#[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+#[cfg(unix)]
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::{env, fs};
 
-pub(crate) struct ShellScript {
+use crate::command::COMMAND_MARKER;
+
+/// The extension of the generated script, which PowerShell requires to be `ps1`.
+#[cfg(unix)]
+const SCRIPT_EXTENSION: &str = "sh";
+#[cfg(windows)]
+const SCRIPT_EXTENSION: &str = "ps1";
+
+/// Whether this process was interrupted, such as with Ctrl-C, while running a shell script.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The process id of the shell script being run, to which the interrupts are forwarded, or `0`
+/// when none is running.
+#[cfg(unix)]
+static RUNNING: AtomicI32 = AtomicI32::new(0);
+
+/// The output of the commands run by [`ShellScript::run_captured`].
+#[derive(Debug)]
+pub struct CapturedOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: ExitStatus,
+}
+
+/// The environment variable holding the absolute path of the directory the shell script was
+/// created for, which is the MARKDOWN file's directory, even when the commands run elsewhere.
+const DIR_ENV: &str = "ME_DIR";
+
+/// The script file that runs the commands, created from the output of
+/// [`CommandBlocks::as_script`](crate::CommandBlocks::as_script).
+#[derive(Debug)]
+pub struct ShellScript {
     path: PathBuf,
+    dir: PathBuf,
+    working_dir: PathBuf,
+    envs: Vec<(String, String)>,
+    keep: bool,
+    prefix_output: bool,
+    shell: Option<PathBuf>,
+    shebang: Option<String>,
+    log: Option<PathBuf>,
 }
 
 impl ShellScript {
-    pub(crate) fn new(directory: &Path, commands: &str) -> Self {
-        let script_path = Self::create_file_path(directory);
+    /// Creates a temporary shell script in the given directory, from where the commands are run.
+    /// The shell script is deleted once this is dropped.  Shell scripts generated with
+    /// [`Options::with_self_delete`](crate::Options::with_self_delete) also delete themselves on
+    /// exit, which covers this process being killed, in which case there is nothing left to delete
+    /// when this is dropped.
+    pub fn new(directory: &Path, commands: &str) -> io::Result<Self> {
+        Self::new_with_prefix(directory, ".me-commands-", commands)
+    }
+
+    /// Creates a temporary shell script, like [`ShellScript::new`], whose file name starts with
+    /// the given prefix, followed by the process id and the time it was created.  The shell script
+    /// is created in the temporary directory instead when it cannot be created in the given
+    /// directory, such as a read-only checkout, and the commands are still run from the given
+    /// directory.  Fails when the shell script cannot be written in either directory.
+    pub fn new_with_prefix(directory: &Path, prefix: &str, commands: &str) -> io::Result<Self> {
+        let mut script_path = Self::create_file_path(directory, prefix);
+        if let Err(e) = Self::write_shell_script(&script_path, commands) {
+            let temp_dir = env::temp_dir();
+            eprintln!(
+                "Failed to create the shell script in {}: {}.  Creating it in {} instead",
+                directory.display(),
+                e,
+                temp_dir.display()
+            );
+            script_path = Self::create_file_path(&temp_dir, prefix);
+            Self::write_shell_script(&script_path, commands)?;
+        }
+
+        Ok(Self::of(script_path, directory, commands, false))
+    }
+
+    /// Creates the shell script at the given path, which is kept once this is dropped.  The
+    /// commands are run from the given directory.  Fails when the shell script cannot be written,
+    /// such as when its directory does not exist.
+    pub fn new_at(path: &Path, directory: &Path, commands: &str) -> io::Result<Self> {
+        Self::create(path.to_path_buf(), directory, commands, true)
+    }
+
+    fn create(
+        script_path: PathBuf,
+        directory: &Path,
+        commands: &str,
+        keep: bool,
+    ) -> io::Result<Self> {
+        Self::write_shell_script(&script_path, commands)?;
+        Ok(Self::of(script_path, directory, commands, keep))
+    }
+
+    fn of(script_path: PathBuf, directory: &Path, commands: &str, keep: bool) -> Self {
+        ShellScript {
+            path: script_path,
+            dir: fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf()),
+            working_dir: directory.to_path_buf(),
+            envs: vec![],
+            keep,
+            prefix_output: false,
+            shell: None,
+            shebang: commands
+                .lines()
+                .next()
+                .and_then(|line| line.strip_prefix("#!"))
+                .map(|interpreter| interpreter.trim().to_string()),
+            log: None,
+        }
+    }
+
+    /// Runs the commands from the given directory instead of the directory provided when the shell
+    /// script was created.
+    pub fn with_working_dir(mut self, working_dir: Option<PathBuf>) -> Self {
+        if let Some(working_dir) = working_dir {
+            self.working_dir = working_dir;
+        }
+        self
+    }
+
+    /// Sets the environment variables of the commands, in addition to those inherited from this
+    /// process and the `ME_DIR` environment variable, which these can replace.
+    pub fn with_envs(mut self, envs: &[(String, String)]) -> Self {
+        self.envs = envs.to_vec();
+        self
+    }
 
-        Self::create_shell_script(&script_path)
-            .write_all(commands.as_bytes())
-            .expect("Failed to create shell script");
+    /// Relays the output of the commands line by line, prefixing each line with the number of the
+    /// command that printed it, such as `[1] `.  The shell script is expected to be generated with
+    /// the output prefixed, so that the lines can be related to their command.
+    pub fn with_prefix_output(mut self, prefix_output: bool) -> Self {
+        self.prefix_output = prefix_output;
+        self
+    }
+
+    /// Writes the output of the commands, both their standard output and standard error, to the
+    /// given file, in addition to printing it.  The file is replaced if it exists.
+    pub fn with_log(mut self, log: Option<PathBuf>) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Runs the shell script using the given shell, such as `/bin/bash`, instead of `/bin/sh`.
+    /// Ignored on Windows, where the PowerShell script is always run using PowerShell.
+    pub fn with_shell(mut self, shell: Option<&Path>) -> Self {
+        self.shell = shell.map(Path::to_path_buf);
+        self
+    }
+
+    /// Runs the commands and returns `true` if all commands succeeded.  An interrupt received
+    /// while the commands run, such as Ctrl-C, is forwarded to the shell script instead of
+    /// terminating this process, such that the shell script is still deleted once dropped, and
+    /// [`ShellScript::interrupted`] returns `true` afterwards.
+    pub fn run(&self) -> bool {
+        let mut command = self.command();
+
+        if !self.prefix_output && self.log.is_none() {
+            let mut child = command.spawn().expect("Failed to execute process");
+            let _interrupts = InterruptForwarder::new(&child);
+            return child.wait().expect("Failed to finish process").success();
+        }
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute process");
+        let _interrupts = InterruptForwarder::new(&child);
+
+        let log = self.log.as_ref().map(|path| {
+            let file = File::create(path).expect("Failed to create the log file");
+            Arc::new(Mutex::new(file))
+        });
+        let stdout = child.stdout.take().expect("Failed to capture stdout");
+        let stderr = child.stderr.take().expect("Failed to capture stderr");
+        let relays = [
+            Self::relay(stdout, log.clone(), |line| print!("{}", line)),
+            Self::relay(stderr, log, |line| eprint!("{}", line)),
+        ];
+
+        let status = child.wait().expect("Failed to finish process");
+        for relay in relays {
+            relay.join().expect("Failed to relay the output");
+        }
+        status.success()
+    }
+
+    /// Runs the commands, capturing their output instead of printing it.  The output is not
+    /// prefixed nor written to the log.
+    pub fn run_captured(&self) -> CapturedOutput {
+        Self::capture(self.command())
+    }
+
+    /// Checks the syntax of the shell script, using the shell's `-n` option, without running the
+    /// commands.  The shell's error messages, if any, are captured as its standard error.  The
+    /// shell script is checked by the shell that runs it, that is, the shell when one is set, the
+    /// interpreter of its shebang, or `/bin/sh` otherwise.
+    #[cfg(unix)]
+    pub fn check(&self) -> CapturedOutput {
+        let mut command = match (&self.shell, &self.shebang) {
+            (Some(shell), _) => Command::new(shell),
+            (None, Some(interpreter)) => {
+                let mut parts = interpreter.split_whitespace();
+                let mut command = Command::new(parts.next().unwrap_or("/bin/sh"));
+                command.args(parts);
+                command
+            }
+            (None, None) => Command::new("/bin/sh"),
+        };
+        command
+            .arg("-n")
+            .arg(&self.path)
+            .current_dir(&self.working_dir);
+        Self::capture(command)
+    }
 
-        ShellScript { path: script_path }
+    /// Checks the syntax of the PowerShell script using the PowerShell parser, without running the
+    /// commands.  The parse errors, if any, are captured as the standard error.
+    #[cfg(windows)]
+    pub fn check(&self) -> CapturedOutput {
+        let path = self.path.display().to_string().replace('\'', "''");
+        let mut command = Command::new("powershell");
+        command.args([
+            "-NoProfile",
+            "-Command",
+            &format!(
+                "$errors = $null; \
+                 [void][System.Management.Automation.Language.Parser]::ParseFile('{path}', [ref]$null, [ref]$errors); \
+                 $errors | ForEach-Object {{ [Console]::Error.WriteLine($_) }}; \
+                 exit [int]($errors.Count -gt 0)"
+            ),
+        ]);
+        Self::capture(command)
     }
 
-    pub(crate) fn run(&self) {
-        Command::new("/bin/sh")
-            .current_dir(&self.current_dir())
-            .args(["-c", &self.path_as_str()])
+    fn capture(mut command: Command) -> CapturedOutput {
+        let child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
-            .expect("Failed to execute process")
-            .wait()
-            .expect("Failed to finish process");
+            .expect("Failed to execute process");
+        let _interrupts = InterruptForwarder::new(&child);
+        let output = child.wait_with_output().expect("Failed to finish process");
+
+        CapturedOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status,
+        }
+    }
+
+    /// Returns `true` if this process was interrupted, such as with Ctrl-C, while running a shell
+    /// script, in which case the remaining shell scripts should not run.
+    pub fn interrupted() -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+
+    fn command(&self) -> Command {
+        let mut command = self.interpreter();
+        command
+            .current_dir(&self.working_dir)
+            .env(DIR_ENV, &self.dir)
+            .envs(self.envs.iter().map(|(key, value)| (key, value)));
+        command
     }
 
+    /// Reads the given output line by line on a separate thread, keeping track of the current
+    /// command from the marker lines and printing every other line prefixed with its number, if
+    /// known, and writing it to the log, if any.  The markers are written to both the standard
+    /// output and error, and each output keeps track of its own current command, as the lines of
+    /// the two outputs are read independently.
+    fn relay<R, F>(output: R, log: Option<Arc<Mutex<File>>>, print: F) -> thread::JoinHandle<()>
+    where
+        R: Read + Send + 'static,
+        F: Fn(&str) + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(output);
+            let mut buffer = Vec::new();
+            let mut current = 0;
+            while reader
+                .read_until(b'\n', &mut buffer)
+                .expect("Failed to read the output")
+                > 0
+            {
+                let line = String::from_utf8_lossy(&buffer);
+                if let Some(number) = line.strip_prefix(COMMAND_MARKER) {
+                    if let Ok(number) = number.trim_end().parse() {
+                        current = number;
+                    }
+                } else {
+                    let line = match current {
+                        0 => line.into_owned(),
+                        number => format!("[{}] {}", number, line),
+                    };
+                    print(&line);
+                    if let Some(log) = &log {
+                        log.lock()
+                            .expect("Failed to lock the log file")
+                            .write_all(line.as_bytes())
+                            .expect("Failed to write to the log file");
+                    }
+                }
+                buffer.clear();
+            }
+        })
+    }
+
+    /// Returns the command that runs the shell script using the shell, when one is set, or using
+    /// `/bin/sh`, which runs the shell script as an executable, honouring its shebang.  The shell
+    /// script replaces `/bin/sh`, such that the interrupts forwarded to the running process reach
+    /// the shell script.  A shell script without a shebang is run by `/bin/sh` itself.
+    #[cfg(unix)]
+    fn interpreter(&self) -> Command {
+        match &self.shell {
+            Some(shell) => {
+                let mut command = Command::new(shell);
+                command.arg(self.path_as_str());
+                command
+            }
+            None if self.shebang.is_none() => {
+                let mut command = Command::new("/bin/sh");
+                command.arg(self.path_as_str());
+                command
+            }
+            None => {
+                let mut command = Command::new("/bin/sh");
+                command.args(["-c", "exec \"$0\"", &self.path_as_str()]);
+                command
+            }
+        }
+    }
+
+    /// Returns the command that runs the PowerShell script, bypassing the execution policy as the
+    /// script is generated and not signed.
+    #[cfg(windows)]
+    fn interpreter(&self) -> Command {
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-ExecutionPolicy", "Bypass", "-File"]);
+        command.arg(&self.path);
+        command
+    }
+
+    #[cfg(unix)]
     fn path_as_str(&self) -> String {
         fs::canonicalize(&self.path)
             .expect("Failed to canonicalize path")
@@ -41,16 +366,19 @@ impl ShellScript {
             .to_string()
     }
 
-    fn current_dir(&self) -> PathBuf {
-        fs::canonicalize(&self.path)
-            .expect("Failed to canonicalize path")
-            .parent()
-            .map(|path| path.to_path_buf())
-            .unwrap_or_else(|| env::current_dir().expect("Failed to fetch the current directory"))
-    }
-
-    fn create_file_path(directory: &Path) -> PathBuf {
-        directory.join(format!(".me-commands-{}.sh", Self::millis_since_epoch()))
+    /// Returns the path of a temporary shell script, which includes the process id such that
+    /// concurrent runs in the same directory do not use the same shell script, and a counter such
+    /// that shell scripts created within the same millisecond do not either.
+    fn create_file_path(directory: &Path, prefix: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        directory.join(format!(
+            "{}{}-{}-{}.{}",
+            prefix,
+            process::id(),
+            Self::millis_since_epoch(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            SCRIPT_EXTENSION
+        ))
     }
 
     fn millis_since_epoch() -> u128 {
@@ -60,30 +388,148 @@ impl ShellScript {
             .as_millis()
     }
 
-    fn create_shell_script(path: &Path) -> File {
-        let shell_script = File::create(path).expect("Failed to create shell script");
-        Self::make_shell_script_executable(&shell_script);
-        shell_script
+    fn write_shell_script(path: &Path, commands: &str) -> io::Result<()> {
+        let mut shell_script = File::create(path)?;
+        Self::make_shell_script_executable(&shell_script)?;
+        shell_script.write_all(commands.as_bytes())
     }
 
-    fn make_shell_script_executable(shell_script: &File) {
-        let metadata = shell_script
-            .metadata()
-            .expect("Failed to get the script metadata");
-
-        let mut permissions = metadata.permissions();
+    #[cfg(unix)]
+    fn make_shell_script_executable(shell_script: &File) -> io::Result<()> {
+        let mut permissions = shell_script.metadata()?.permissions();
         permissions.set_mode(0o755);
+        shell_script.set_permissions(permissions)
+    }
 
-        shell_script
-            .set_permissions(permissions)
-            .expect("Failed to set the script permissions");
+    /// Scripts are run through PowerShell on Windows and do not need to be executable.
+    #[cfg(windows)]
+    fn make_shell_script_executable(_shell_script: &File) -> io::Result<()> {
+        Ok(())
     }
 }
 
 impl Drop for ShellScript {
     fn drop(&mut self) {
-        if fs::remove_file(&self.path).is_err() {
-            eprintln!("Failed to delete the auto generated shell script");
+        if self.keep {
+            return;
+        }
+
+        /* The shell script may have already deleted itself on exit */
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                eprintln!("Failed to delete the auto generated shell script")
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Forwards the interrupts received by this process to the running shell script, instead of
+/// terminating this process before the shell script is deleted, until this is dropped.  The shell
+/// script may also delete itself on exit, so deleting it is safe to do twice.
+struct InterruptForwarder {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl InterruptForwarder {
+    #[cfg(unix)]
+    fn new(child: &Child) -> Self {
+        RUNNING.store(child.id() as i32, Ordering::SeqCst);
+        let handler = Self::forward as extern "C" fn(libc::c_int);
+        /* SAFETY: the handler only uses atomics and kill(), which are async-signal-safe */
+        let previous = unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+
+        /* Keep ignoring the interrupts when these were ignored, such as when run with nohup */
+        if previous == libc::SIG_IGN {
+            unsafe { libc::signal(libc::SIGINT, previous) };
         }
+        InterruptForwarder { previous }
+    }
+
+    #[cfg(windows)]
+    fn new(_child: &Child) -> Self {
+        InterruptForwarder {}
+    }
+
+    #[cfg(unix)]
+    extern "C" fn forward(signal: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        let pid = RUNNING.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+impl Drop for InterruptForwarder {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            unsafe { libc::signal(libc::SIGINT, self.previous) };
+            RUNNING.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_shell_scripts_in_same_directory() {
+        let dir = Path::new("./target/fixtures/create_shell_scripts_in_same_directory");
+        fs::create_dir_all(dir).expect("Failed to create the fixtures directory");
+
+        let first =
+            ShellScript::new(dir, "echo 'First'\n").expect("Failed to create the shell script");
+        let second =
+            ShellScript::new(dir, "echo 'Second'\n").expect("Failed to create the shell script");
+        assert_ne!(first.path, second.path);
+
+        let second_path = second.path.clone();
+        drop(first);
+        assert_eq!(
+            "echo 'Second'\n",
+            fs::read_to_string(&second_path).expect("Failed to read the second shell script")
+        );
+
+        drop(second);
+        assert!(!second_path.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn create_shell_script_in_temp_dir_when_directory_is_not_writable() {
+        let fixtures = Path::new("./target/fixtures/create_shell_script_in_temp_dir");
+        fs::create_dir_all(fixtures).expect("Failed to create the fixtures directory");
+
+        /* Unlike a read-only directory, a file cannot hold a shell script even when run as root */
+        let dir = fixtures.join("not-a-directory");
+        fs::write(&dir, "").expect("Failed to create the fixture file");
+
+        let script = ShellScript::new(&dir, "#!/bin/sh\necho 'Hello'\n")
+            .expect("Failed to create the shell script");
+        assert_eq!(Some(env::temp_dir().as_path()), script.path.parent());
+        assert_eq!(dir, script.working_dir);
+        assert!(script.path.is_file());
+
+        let path = script.path.clone();
+        drop(script);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_captured_shell_script() {
+        let dir = Path::new("./target/fixtures/run_captured_shell_script");
+        fs::create_dir_all(dir).expect("Failed to create the fixtures directory");
+
+        let script = ShellScript::new(dir, "#!/bin/sh\necho 'Hello'\necho 'Oops' >&2\nexit 3\n")
+            .expect("Failed to create the shell script");
+        let output = script.run_captured();
+        assert_eq!("Hello\n", output.stdout);
+        assert_eq!("Oops\n", output.stderr);
+        assert_eq!(Some(3), output.status.code());
     }
 }